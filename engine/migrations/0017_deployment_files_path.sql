@@ -0,0 +1,2 @@
+-- Index used by the serving path to resolve a request path to a file in a deployment
+CREATE INDEX idx_deployment_files_path ON deployment_files (deployment_id, file_path);
@@ -0,0 +1,21 @@
+-- The deployment that is currently live for a site
+ALTER TABLE sites ADD COLUMN active_deployment_id TEXT REFERENCES deployments(deployment_id) ON DELETE SET NULL;
+
+-- History of promoted deployments per site, the newest row is the live one
+-- rolling back pops the newest row and re-activates the one before it
+CREATE TABLE deployment_promotions (
+    promotion_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    site_id TEXT NOT NULL REFERENCES sites(site_id) ON DELETE CASCADE,
+    deployment_id TEXT NOT NULL REFERENCES deployments(deployment_id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_deployment_promotions_site ON deployment_promotions (site_id, promotion_id);
+
+-- Existing sites keep serving their newest deployment
+UPDATE sites s SET active_deployment_id = (
+    SELECT d.deployment_id FROM deployments d WHERE d.site_id = s.site_id ORDER BY d.created_at DESC LIMIT 1
+);
+
+INSERT INTO deployment_promotions (site_id, deployment_id)
+SELECT site_id, active_deployment_id FROM sites WHERE active_deployment_id IS NOT NULL;
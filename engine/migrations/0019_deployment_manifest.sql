@@ -0,0 +1,10 @@
+-- Files announced by a client for a deployment before their blobs are uploaded
+-- rows are moved into `deployment_files` when the deployment is finalized
+CREATE TABLE deployment_manifest_files (
+    deployment_id TEXT NOT NULL REFERENCES deployments(deployment_id) ON DELETE CASCADE,
+    file_path TEXT NOT NULL,
+    file_hash TEXT NOT NULL,
+    file_size BIGINT NOT NULL,
+    mime_type TEXT NOT NULL,
+    PRIMARY KEY (deployment_id, file_path)
+);
@@ -0,0 +1,8 @@
+-- Lifecycle status of a deployment, existing deployments are considered ready
+ALTER TABLE deployments ADD COLUMN status TEXT NOT NULL DEFAULT 'ready';
+ALTER TABLE deployments ALTER COLUMN status SET DEFAULT 'created';
+-- Reason for a failed deployment
+ALTER TABLE deployments ADD COLUMN status_message TEXT;
+-- Sub states for the car/ipfs pipeline and the preview (bunshot) pipeline
+ALTER TABLE deployments ADD COLUMN ipfs_status TEXT;
+ALTER TABLE deployments ADD COLUMN preview_status TEXT;
@@ -0,0 +1,4 @@
+-- Blobs younger than the gc grace period are never collected, this protects uploads that are still being linked
+ALTER TABLE files ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+-- Set once the car and preview artifacts of an expired deployment have been removed
+ALTER TABLE deployments ADD COLUMN artifacts_deleted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,16 @@
+-- Per site retention, NULL keeps deployments forever
+-- a deployment is removed once it is neither among the newest `retention_keep_last` nor younger than `retention_days`
+ALTER TABLE sites ADD COLUMN retention_keep_last INTEGER;
+ALTER TABLE sites ADD COLUMN retention_days INTEGER;
+
+-- Pinned deployments are never removed by retention or garbage collection
+ALTER TABLE deployments ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Removing a deployment removes its previews
+ALTER TABLE deployment_previews DROP CONSTRAINT deployment_previews_deployment_id_fkey;
+
+ALTER TABLE deployment_previews
+    ADD CONSTRAINT deployment_previews_deployment_id_fkey
+    FOREIGN KEY (deployment_id)
+    REFERENCES deployments(deployment_id)
+    ON DELETE CASCADE;
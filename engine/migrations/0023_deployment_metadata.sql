@@ -0,0 +1,37 @@
+-- Structured source control and CI metadata, previously only available inside the free-form `context`
+ALTER TABLE deployments ADD COLUMN commit_sha TEXT;
+ALTER TABLE deployments ADD COLUMN branch TEXT;
+ALTER TABLE deployments ADD COLUMN commit_message TEXT;
+ALTER TABLE deployments ADD COLUMN commit_author TEXT;
+ALTER TABLE deployments ADD COLUMN ci_provider TEXT;
+ALTER TABLE deployments ADD COLUMN ci_run_url TEXT;
+ALTER TABLE deployments ADD COLUMN environment TEXT;
+
+CREATE INDEX idx_deployments_site_branch ON deployments (site_id, branch, created_at DESC);
+CREATE INDEX idx_deployments_site_environment ON deployments (site_id, environment, created_at DESC);
+
+-- Backfill from contexts sent by the github action, contexts that aren't valid json are skipped
+DO $$
+DECLARE
+    row RECORD;
+    data JSONB;
+BEGIN
+    FOR row IN SELECT deployment_id, context FROM deployments WHERE context IS NOT NULL LOOP
+        BEGIN
+            IF row.context::jsonb ->> 'contextType' = 'github-action' THEN
+                data := row.context::jsonb -> 'data';
+
+                UPDATE deployments SET
+                    commit_sha = lower(data ->> 'sha'),
+                    branch = regexp_replace(data ->> 'ref', '^refs/heads/', ''),
+                    commit_message = data -> 'commit' ->> 'message',
+                    commit_author = data -> 'commit' -> 'author' ->> 'name',
+                    ci_provider = 'github',
+                    ci_run_url = regexp_replace(data -> 'commit' ->> 'url', '/commit/.*$', '') || '/actions/runs/' || (data ->> 'runId')
+                WHERE deployment_id = row.deployment_id;
+            END IF;
+        EXCEPTION WHEN others THEN
+            NULL;
+        END;
+    END LOOP;
+END $$;
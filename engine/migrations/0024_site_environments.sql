@@ -0,0 +1,13 @@
+-- Named environments of a site (`staging`, `pr-123`, ...) each pointing at one of its deployments
+CREATE TABLE site_environments (
+    site_id TEXT NOT NULL REFERENCES sites(site_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    deployment_id TEXT REFERENCES deployments(deployment_id) ON DELETE SET NULL,
+    branch TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (site_id, name)
+);
+
+-- Domains bound to an environment serve its deployment, NULL serves the live deployment
+ALTER TABLE domains ADD COLUMN environment TEXT;
@@ -0,0 +1,6 @@
+-- Redirect and rewrite rules compiled from the `_redirects` / `edgeserver.json` file of a deployment
+CREATE TABLE deployment_rules (
+    deployment_id TEXT PRIMARY KEY REFERENCES deployments(deployment_id) ON DELETE CASCADE,
+    redirects JSONB NOT NULL DEFAULT '[]',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+-- Response header rules compiled from the `_headers` / `edgeserver.json` file of a deployment
+ALTER TABLE deployment_rules ADD COLUMN headers JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,4 @@
+-- How request paths are mapped onto the files of a deployment
+ALTER TABLE sites ADD COLUMN clean_urls BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE sites ADD COLUMN trailing_slash TEXT NOT NULL DEFAULT 'ignore';
+ALTER TABLE sites ADD COLUMN index_file TEXT NOT NULL DEFAULT 'index.html';
@@ -0,0 +1,2 @@
+-- Serve the index file instead of the 404 page for paths that don't exist, for single page apps
+ALTER TABLE sites ADD COLUMN spa_mode BOOLEAN NOT NULL DEFAULT FALSE;
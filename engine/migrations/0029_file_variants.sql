@@ -0,0 +1,9 @@
+-- Precompressed variants of compressible files, stored in the bucket under `variants/<variant_hash>`
+CREATE TABLE file_variants (
+    file_id BIGINT NOT NULL REFERENCES files(file_id) ON DELETE CASCADE,
+    encoding TEXT NOT NULL,
+    variant_hash TEXT NOT NULL,
+    variant_size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (file_id, encoding)
+);
@@ -0,0 +1,15 @@
+-- Access controls of a site, a NULL environment protects every domain and preview of the site,
+-- otherwise only the domains bound to that environment
+CREATE TABLE site_access (
+    site_id TEXT NOT NULL REFERENCES sites(site_id) ON DELETE CASCADE,
+    environment TEXT,
+    basic_auth_username TEXT,
+    basic_auth_password_hash TEXT,
+    password_hash TEXT,
+    cookie_secret TEXT NOT NULL,
+    allowed_ips INET[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX site_access_scope ON site_access (site_id, (COALESCE(environment, '')));
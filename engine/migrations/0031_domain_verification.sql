@@ -0,0 +1,4 @@
+-- Outcome of the dns challenge checks of a pending domain
+ALTER TABLE domains_pending ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE domains_pending ADD COLUMN last_attempt_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE domains_pending ADD COLUMN failure_reason TEXT;
@@ -0,0 +1,9 @@
+-- Pending domains are checked in the background with backoff
+ALTER TABLE domains_pending ADD COLUMN next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP;
+CREATE INDEX domains_pending_next_attempt_at ON domains_pending (next_attempt_at);
+
+-- Active domains are re-checked to still point at the engine, the challenge they were verified with is kept
+ALTER TABLE domains ADD COLUMN challenge TEXT;
+ALTER TABLE domains ADD COLUMN last_checked_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE domains ADD COLUMN check_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE domains ADD COLUMN failure_reason TEXT;
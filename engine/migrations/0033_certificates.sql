@@ -0,0 +1,27 @@
+-- ACME accounts, one per directory, the account key is encrypted with ACME_ENCRYPTION_KEY
+CREATE TABLE acme_accounts (
+    directory_url TEXT PRIMARY KEY,
+    account_url TEXT NOT NULL,
+    key_encrypted TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Certificates of verified domains, removed along with the domain
+CREATE TABLE certificates (
+    domain VARCHAR(255) PRIMARY KEY REFERENCES domains(domain) ON DELETE CASCADE,
+    -- PEM chain, NULL until the first issuance succeeded
+    certificate_pem TEXT,
+    key_encrypted TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    last_attempt_at TIMESTAMP WITH TIME ZONE,
+    failure_reason TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Key authorizations answered at /.well-known/acme-challenge/<token> while an HTTP-01 challenge is validated
+CREATE TABLE acme_http_challenges (
+    token TEXT PRIMARY KEY,
+    key_authorization TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,24 @@
+-- Domains are stored lowercased and without a trailing dot, names added before are brought into that form
+-- Certificates of renamed domains are dropped, they are issued again for the new name
+DELETE FROM certificates WHERE domain <> LOWER(RTRIM(domain, '.'));
+
+-- Of the domains only differing in case the oldest is kept
+DELETE FROM domains d
+USING domains other
+WHERE LOWER(RTRIM(d.domain, '.')) = LOWER(RTRIM(other.domain, '.'))
+AND (d.created_at, d.domain) > (other.created_at, other.domain);
+
+UPDATE domains SET domain = LOWER(RTRIM(domain, '.')) WHERE domain <> LOWER(RTRIM(domain, '.'));
+
+DELETE FROM domains_pending p
+USING domains_pending other
+WHERE p.site_id = other.site_id
+AND LOWER(RTRIM(p.domain, '.')) = LOWER(RTRIM(other.domain, '.'))
+AND (p.created_at, p.domain) > (other.created_at, other.domain);
+
+UPDATE domains_pending SET domain = LOWER(RTRIM(domain, '.')) WHERE domain <> LOWER(RTRIM(domain, '.'));
+
+-- Pending entries for a name their site already serves
+DELETE FROM domains_pending p
+USING domains d
+WHERE d.site_id = p.site_id AND d.domain = p.domain;
@@ -0,0 +1,2 @@
+-- Car and preview artifacts are removed together with their deployment, there is no state left to track
+ALTER TABLE deployments DROP COLUMN artifacts_deleted;
@@ -0,0 +1,338 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use color_eyre::eyre::{eyre, Result};
+use reqwest::{Client, Response};
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub const HTTP_01: &str = "http-01";
+pub const DNS_01: &str = "dns-01";
+
+/// Status of an order, authorization or challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Order {
+    pub status: Status,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+    pub error: Option<Problem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Authorization {
+    /// The domain being authorized, without the `*.` of wildcard orders
+    pub identifier: Identifier,
+    pub status: Status,
+    pub challenges: Vec<Challenge>,
+    #[serde(default)]
+    pub wildcard: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    pub token: String,
+    pub status: Status,
+    pub error: Option<Problem>,
+}
+
+/// An RFC 7807 problem document, returned for failed requests and challenges
+#[derive(Debug, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.kind.as_deref().unwrap_or("unknown"),
+            self.detail.as_deref().unwrap_or("no detail")
+        )
+    }
+}
+
+/// The public part of the account key, in the member order RFC 7638 thumbprints are computed over
+#[derive(Serialize)]
+struct Jwk {
+    crv: &'static str,
+    kty: &'static str,
+    x: String,
+    y: String,
+}
+
+#[derive(Serialize)]
+struct Protected<'a> {
+    alg: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jwk: Option<Jwk>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'a str>,
+    nonce: &'a str,
+    url: &'a str,
+}
+
+/// A minimal RFC 8555 client, requests are signed with an ES256 account key
+pub struct AcmeClient {
+    http: Client,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    account_url: Option<String>,
+}
+
+/// Generates a PKCS#8 encoded account key
+pub fn generate_account_key() -> Result<Vec<u8>> {
+    let document = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .map_err(|_| eyre!("Failed to generate an ACME account key"))?;
+
+    Ok(document.as_ref().to_vec())
+}
+
+/// The TXT record value for a DNS-01 challenge
+pub fn dns_value(key_authorization: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, key_authorization.as_bytes()))
+}
+
+impl AcmeClient {
+    /// Loads the directory, `account_url` is the account registered for `account_key` if there is one
+    pub async fn new(http: Client, directory_url: &str, account_key: &[u8], account_url: Option<String>) -> Result<Self> {
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &SystemRandom::new())
+            .map_err(|error| eyre!("Invalid ACME account key: {}", error))?;
+
+        let directory = http
+            .get(directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Directory>()
+            .await?;
+
+        Ok(Self {
+            http,
+            directory,
+            key,
+            account_url,
+        })
+    }
+
+    /// Registers the account key, agreeing to the terms of service, returns the account url
+    pub async fn register(&mut self, contact: Option<&str>) -> Result<String> {
+        let contact: Vec<String> = contact.map(|email| format!("mailto:{}", email)).into_iter().collect();
+
+        let response = self
+            .post(
+                &self.directory.new_account,
+                Some(json!({
+                    "termsOfServiceAgreed": true,
+                    "contact": contact,
+                })),
+            )
+            .await?;
+
+        let account_url = location(&response)?;
+        self.account_url = Some(account_url.clone());
+
+        Ok(account_url)
+    }
+
+    /// Places an order for a certificate covering `domain`, returns the order url and the order
+    pub async fn new_order(&self, domain: &str) -> Result<(String, Order)> {
+        let response = self
+            .post(
+                &self.directory.new_order,
+                Some(json!({
+                    "identifiers": [Identifier { kind: "dns".to_string(), value: domain.to_string() }],
+                })),
+            )
+            .await?;
+
+        let order_url = location(&response)?;
+
+        Ok((order_url, response.json().await?))
+    }
+
+    pub async fn order(&self, url: &str) -> Result<Order> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    pub async fn authorization(&self, url: &str) -> Result<Authorization> {
+        Ok(self.post(url, None).await?.json().await?)
+    }
+
+    /// Tells the server the challenge can be validated
+    pub async fn challenge_ready(&self, url: &str) -> Result<()> {
+        self.post(url, Some(json!({}))).await?;
+
+        Ok(())
+    }
+
+    pub async fn finalize(&self, url: &str, csr_der: &[u8]) -> Result<Order> {
+        Ok(self
+            .post(url, Some(json!({ "csr": URL_SAFE_NO_PAD.encode(csr_der) })))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Downloads the PEM chain of an issued certificate
+    pub async fn certificate(&self, url: &str) -> Result<String> {
+        Ok(self.post(url, None).await?.text().await?)
+    }
+
+    /// The response to a challenge, its token joined with the thumbprint of the account key
+    pub fn key_authorization(&self, token: &str) -> Result<String> {
+        let thumbprint = serde_json::to_vec(&self.jwk())?;
+
+        Ok(format!("{}.{}", token, URL_SAFE_NO_PAD.encode(digest(&SHA256, &thumbprint))))
+    }
+
+    fn jwk(&self) -> Jwk {
+        // uncompressed point, 0x04 followed by x and y
+        let (x, y) = self.key.public_key().as_ref()[1..].split_at(32);
+
+        Jwk {
+            crv: "P-256",
+            kty: "EC",
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y),
+        }
+    }
+
+    async fn nonce(&self) -> Result<String> {
+        let response = self.http.head(&self.directory.new_nonce).send().await?.error_for_status()?;
+
+        response
+            .headers()
+            .get("replay-nonce")
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+            .ok_or_else(|| eyre!("ACME server did not return a nonce"))
+    }
+
+    /// Sends a JWS signed request, `None` makes it a POST-as-GET
+    async fn post(&self, url: &str, payload: Option<serde_json::Value>) -> Result<Response> {
+        let nonce = self.nonce().await?;
+
+        // only account creation is signed with the key itself, everything else names the account
+        let protected = Protected {
+            alg: "ES256",
+            jwk: self.account_url.is_none().then(|| self.jwk()),
+            kid: self.account_url.as_deref(),
+            nonce: &nonce,
+            url,
+        };
+
+        let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&protected)?);
+        let payload = match payload {
+            Some(payload) => URL_SAFE_NO_PAD.encode(serde_json::to_vec(&payload)?),
+            None => String::new(),
+        };
+
+        let signature = self
+            .key
+            .sign(&SystemRandom::new(), format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| eyre!("Failed to sign ACME request"))?;
+
+        let response = self
+            .http
+            .post(url)
+            .header("content-type", "application/jose+json")
+            .json(&json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let problem = response
+                .json::<Problem>()
+                .await
+                .map(|problem| problem.to_string())
+                .unwrap_or_default();
+
+            return Err(eyre!("ACME request to {} failed with {}: {}", url, status, problem));
+        }
+
+        Ok(response)
+    }
+}
+
+fn location(response: &Response) -> Result<String> {
+    response
+        .headers()
+        .get("location")
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+        .ok_or_else(|| eyre!("ACME server did not return a location"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_value() {
+        // RFC 8555 section 8.4, base64url(sha256(key authorization)) without padding
+        let value = dns_value("token.thumbprint");
+
+        assert_eq!(value.len(), 43);
+        assert!(!value.contains('='));
+        assert_eq!(value, dns_value("token.thumbprint"));
+        assert_ne!(value, dns_value("token.other"));
+    }
+
+    #[test]
+    fn test_problem_display() {
+        let problem: Problem = serde_json::from_str(
+            r#"{"type": "urn:ietf:params:acme:error:unauthorized", "detail": "Incorrect TXT record"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            problem.to_string(),
+            "urn:ietf:params:acme:error:unauthorized: Incorrect TXT record"
+        );
+    }
+}
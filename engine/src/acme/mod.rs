@@ -0,0 +1,292 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Result};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::{
+    models::certificate::{AcmeAccount, AcmeHttpChallenge, Certificate},
+    state::{AcmeConfig, State},
+    tls::{certified_key, expires_at},
+    utils::encryption::{decrypt, encrypt},
+};
+
+use self::client::{dns_value, generate_account_key, AcmeClient, Authorization, Status, DNS_01, HTTP_01};
+
+pub mod client;
+
+/// Certificates issued per run
+const BATCH_SIZE: i64 = 10;
+/// Orders and authorizations are polled this often while the server validates them
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// Issues and renews a certificate for every verified domain and keeps `AppState.tls` in sync with the database
+///
+/// Runs every `ACME_INTERVAL_MINUTES` when `ACME_ENCRYPTION_KEY` is set
+pub async fn run_scheduled(state: State) {
+    let Some(config) = &state.config.acme else {
+        info!("ACME is disabled, set ACME_ENCRYPTION_KEY to issue certificates for verified domains");
+        return;
+    };
+
+    let key = match config.encryption_key() {
+        Ok(key) => key,
+        Err(error) => {
+            error!("ACME is disabled: {}", error);
+            return;
+        }
+    };
+
+    loop {
+        if let Err(error) = load_certificates(&state, &key).await {
+            error!("Loading certificates failed: {:?}", error);
+        }
+
+        if let Err(error) = issue_due(&state, config, &key).await {
+            error!("Issuing certificates failed: {:?}", error);
+        }
+
+        async_std::task::sleep(Duration::from_secs(config.interval_minutes * 60)).await;
+    }
+}
+
+/// Replaces the certificates served with the ones in the database, picks up those issued by other engines
+async fn load_certificates(state: &State, key: &[u8; 32]) -> Result<()> {
+    let mut certificates = HashMap::new();
+
+    for certificate in Certificate::get_issued(&state.database).await? {
+        let (Some(certificate_pem), Some(key_encrypted)) = (&certificate.certificate_pem, &certificate.key_encrypted) else {
+            continue;
+        };
+
+        let loaded = decrypt(key, key_encrypted)
+            .map_err(|_| eyre!("Failed to decrypt the key, was ACME_ENCRYPTION_KEY changed?"))
+            .and_then(|key_pem| certified_key(certificate_pem, &key_pem));
+
+        match loaded {
+            Ok(certified) => {
+                certificates.insert(certificate.domain, Arc::new(certified));
+            }
+            Err(error) => warn!("Skipping the certificate of {}: {}", certificate.domain, error),
+        }
+    }
+
+    state.tls.replace(certificates);
+
+    Ok(())
+}
+
+async fn issue_due(state: &State, config: &AcmeConfig, key: &[u8; 32]) -> Result<()> {
+    let now = Utc::now();
+    let renew_cutoff = now + chrono::Duration::days(config.renew_before_days);
+    let retry_cutoff = now - chrono::Duration::minutes(config.retry_minutes);
+
+    // wildcards can only be issued over DNS-01, which needs the webhook
+    let include_wildcards = config.dns_webhook_url.is_some();
+    let due = Certificate::due(&state.database, renew_cutoff, retry_cutoff, include_wildcards, BATCH_SIZE).await?;
+
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let client = account_client(state, config, key).await?;
+
+    for domain in due {
+        let issued = match issue(state, config, &client, &domain).await {
+            Ok((certificate_pem, key_pem)) => store(state, key, &domain, &certificate_pem, &key_pem).await,
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = issued {
+            warn!("Issuing a certificate for {} failed: {:?}", domain, error);
+
+            // the domain may have been removed meanwhile, that shouldn't hold up the rest of the batch
+            if let Err(error) = Certificate::record_failure(&state.database, &domain, &error.to_string()).await {
+                warn!("Recording the failed certificate for {} failed: {:?}", domain, error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves an issued certificate and starts serving it
+async fn store(state: &State, key: &[u8; 32], domain: &str, certificate_pem: &str, key_pem: &str) -> Result<()> {
+    let expires_at = expires_at(certificate_pem)?;
+    let certified = certified_key(certificate_pem, key_pem.as_bytes())?;
+    let key_encrypted = encrypt(key, key_pem.as_bytes()).map_err(|_| eyre!("Failed to encrypt the key"))?;
+
+    Certificate::store(&state.database, domain, certificate_pem, &key_encrypted, expires_at).await?;
+    state.tls.insert(domain, Arc::new(certified));
+
+    info!("Issued a certificate for {}, valid until {}", domain, expires_at);
+
+    Ok(())
+}
+
+/// A client for the account at the configured directory, the account is registered on first use
+async fn account_client(state: &State, config: &AcmeConfig, key: &[u8; 32]) -> Result<AcmeClient> {
+    let http = config.http_client()?;
+
+    if let Some(account) = AcmeAccount::get(&state.database, &config.directory_url).await? {
+        let account_key = decrypt(key, &account.key_encrypted)
+            .map_err(|_| eyre!("Failed to decrypt the ACME account key, was ACME_ENCRYPTION_KEY changed?"))?;
+
+        return AcmeClient::new(http, &config.directory_url, &account_key, Some(account.account_url)).await;
+    }
+
+    let account_key = generate_account_key()?;
+    let mut client = AcmeClient::new(http, &config.directory_url, &account_key, None).await?;
+    let account_url = client.register(config.contact.as_deref()).await?;
+
+    let key_encrypted = encrypt(key, &account_key).map_err(|_| eyre!("Failed to encrypt the ACME account key"))?;
+    AcmeAccount::create(&state.database, &config.directory_url, &account_url, &key_encrypted).await?;
+
+    info!("Registered ACME account {}", account_url);
+
+    Ok(client)
+}
+
+/// Orders a certificate for `domain`, solving its challenges, returns the PEM chain and PEM private key
+async fn issue(state: &State, config: &AcmeConfig, client: &AcmeClient, domain: &str) -> Result<(String, String)> {
+    let (order_url, order) = client.new_order(domain).await?;
+
+    for authorization_url in &order.authorizations {
+        let authorization = client.authorization(authorization_url).await?;
+
+        if authorization.status == Status::Valid {
+            continue;
+        }
+
+        solve(state, config, client, authorization_url, &authorization).await?;
+    }
+
+    let key_pair = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![domain.to_string()])?;
+    params.distinguished_name = DistinguishedName::new();
+    let csr = params.serialize_request(&key_pair)?;
+
+    let mut order = client.finalize(&order.finalize, csr.der()).await?;
+
+    for _ in 0..POLL_ATTEMPTS {
+        match order.status {
+            Status::Valid => break,
+            Status::Invalid => {
+                return Err(eyre!(
+                    "Order was rejected: {}",
+                    order.error.map(|problem| problem.to_string()).unwrap_or_default()
+                ))
+            }
+            _ => {
+                async_std::task::sleep(POLL_INTERVAL).await;
+                order = client.order(&order_url).await?;
+            }
+        }
+    }
+
+    let certificate_url = order
+        .certificate
+        .ok_or_else(|| eyre!("Order was not completed in time"))?;
+
+    Ok((client.certificate(&certificate_url).await?, key_pair.serialize_pem()))
+}
+
+/// Completes one authorization, with DNS-01 for wildcards (or everything when `ACME_PREFER_DNS_01` is set) and HTTP-01 otherwise
+async fn solve(
+    state: &State,
+    config: &AcmeConfig,
+    client: &AcmeClient,
+    authorization_url: &str,
+    authorization: &Authorization,
+) -> Result<()> {
+    let use_dns = authorization.wildcard || (config.prefer_dns_01 && config.dns_webhook_url.is_some());
+    let kind = if use_dns { DNS_01 } else { HTTP_01 };
+
+    let challenge = authorization
+        .challenges
+        .iter()
+        .find(|challenge| challenge.kind == kind)
+        .ok_or_else(|| eyre!("No {} challenge offered for {}", kind, authorization.identifier.value))?;
+
+    let key_authorization = client.key_authorization(&challenge.token)?;
+    let record_name = format!("_acme-challenge.{}", authorization.identifier.value);
+
+    if use_dns {
+        let webhook_url = config
+            .dns_webhook_url
+            .as_deref()
+            .ok_or_else(|| eyre!("Wildcard domains need DNS-01, set ACME_DNS_WEBHOOK_URL"))?;
+
+        dns_webhook(webhook_url, "present", &record_name, &dns_value(&key_authorization)).await?;
+        async_std::task::sleep(Duration::from_secs(config.dns_propagation_seconds)).await;
+    } else {
+        AcmeHttpChallenge::create(&state.database, &challenge.token, &key_authorization).await?;
+    }
+
+    let result = async {
+        client.challenge_ready(&challenge.url).await?;
+        wait_for_authorization(client, authorization_url).await
+    }
+    .await;
+
+    let cleanup = if use_dns {
+        let webhook_url = config.dns_webhook_url.as_deref().unwrap_or_default();
+        dns_webhook(webhook_url, "cleanup", &record_name, &dns_value(&key_authorization)).await
+    } else {
+        AcmeHttpChallenge::delete(&state.database, &challenge.token)
+            .await
+            .map_err(Into::into)
+    };
+
+    if let Err(error) = cleanup {
+        warn!("Cleaning up the {} challenge of {} failed: {:?}", kind, authorization.identifier.value, error);
+    }
+
+    result
+}
+
+async fn wait_for_authorization(client: &AcmeClient, authorization_url: &str) -> Result<()> {
+    for _ in 0..POLL_ATTEMPTS {
+        let authorization = client.authorization(authorization_url).await?;
+
+        match authorization.status {
+            Status::Valid => return Ok(()),
+            Status::Pending | Status::Processing => async_std::task::sleep(POLL_INTERVAL).await,
+            status => {
+                let problem = authorization
+                    .challenges
+                    .iter()
+                    .find_map(|challenge| challenge.error.as_ref())
+                    .map(|problem| problem.to_string())
+                    .unwrap_or_default();
+
+                return Err(eyre!(
+                    "Authorization of {} is {:?}: {}",
+                    authorization.identifier.value,
+                    status,
+                    problem
+                ));
+            }
+        }
+    }
+
+    Err(eyre!("Authorization was not validated in time"))
+}
+
+/// Asks the DNS webhook to `present` or `cleanup` a TXT record, the request body is `{"fqdn", "value"}`
+async fn dns_webhook(webhook_url: &str, action: &str, record_name: &str, value: &str) -> Result<()> {
+    reqwest::Client::new()
+        .post(format!("{}/{}", webhook_url.trim_end_matches('/'), action))
+        .json(&json!({
+            "fqdn": format!("{}.", record_name),
+            "value": value,
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, io::SeekFrom};
 
+use futures::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -8,6 +9,8 @@ use tracing::info;
 
 use crate::{models::deployment::NewlyCreatedFile, state::State};
 
+pub mod variants;
+
 #[derive(Serialize, Deserialize, Debug, Object)]
 pub struct AssetFile {
     pub path: String,
@@ -23,46 +26,168 @@ impl AssetFile {
     #[tracing::instrument(name = "from_buffer", skip(state, buffer))]
     pub async fn from_buffer(state: &State, buffer: &[u8], path: impl AsRef<str> + Debug) -> Result<(Self, NewlyCreatedFile, String, String, i64), sqlx::Error> {
         let file_hash = hash_file(&buffer);
-        let content_type = infer::get(&buffer)
-            .map(|t| t.mime_type().to_string()).unwrap_or_else(|| {
-                content_type_from_file_name(path.as_ref())
-            });
+        let content_type = content_type(&buffer, path.as_ref());
 
         let file_size = buffer.len() as i64;
 
-        let newly_created_file = query_as!(
+        let newly_created_file = AssetFile::catalog(state, &file_hash, file_size).await?;
+
+        let file = AssetFile {
+            path: file_hash.to_string()
+        };
+
+        Ok((file, newly_created_file, file_hash, content_type, file_size))
+    }
+
+    /// Same as `from_buffer` but for a file that has already been spooled to disk
+    #[tracing::instrument(name = "from_spooled", skip(state, spooled))]
+    pub async fn from_spooled(state: &State, spooled: &SpooledFile, path: impl AsRef<str> + Debug) -> Result<(Self, NewlyCreatedFile, String, String, i64), sqlx::Error> {
+        let content_type = content_type(&spooled.head, path.as_ref());
+
+        let newly_created_file = AssetFile::catalog(state, &spooled.file_hash, spooled.file_size).await?;
+
+        let file = AssetFile {
+            path: spooled.file_hash.to_string()
+        };
+
+        Ok((file, newly_created_file, spooled.file_hash.clone(), content_type, spooled.file_size))
+    }
+
+    /// Stores a blob uploaded outside of an archive (see the deployment manifest flow)
+    ///
+    /// Unlike `catalog` this revives files that were previously garbage collected
+    pub async fn store_spooled(state: &State, spooled: &mut SpooledFile) -> Result<NewlyCreatedFile, color_eyre::eyre::Error> {
+        let content_type = content_type(&spooled.head, "");
+
+        state
+            .storage
+            .bucket
+            .put_object_stream_with_content_type(&mut spooled.file, &spooled.file_hash, &content_type)
+            .await?;
+
+        let file = query_as!(
+            NewlyCreatedFile,
+            r#"
+            INSERT INTO files (file_hash, file_size)
+            VALUES ($1, $2)
+            ON CONFLICT (file_hash) DO UPDATE SET file_deleted = FALSE, created_at = NOW()
+            RETURNING file_id, (xmax = 0) AS is_new
+            "#,
+            spooled.file_hash,
+            spooled.file_size
+        )
+        .fetch_one(&state.database.pool)
+        .await?;
+
+        Ok(file)
+    }
+
+    /// Registers a file hash in the `files` table, `is_new` tells wether the blob still has to be uploaded
+    ///
+    /// Reusing a file moves its `created_at` forward so garbage collection keeps it through the grace period
+    async fn catalog(state: &State, file_hash: &str, file_size: i64) -> Result<NewlyCreatedFile, sqlx::Error> {
+        // a file garbage collection deletes in the meantime isn't touched, the second attempt registers it again
+        for _ in 0..2 {
+            let newly_created_file = query_as!(
                 NewlyCreatedFile,
                 r#"
                 WITH ins AS (
-      INSERT INTO files (file_hash, file_size)
-      VALUES ($1, $2)
-      ON CONFLICT (file_hash) DO NOTHING
-      RETURNING file_id, true AS is_new
-    )
-    SELECT file_id, is_new
-    FROM ins
-    UNION ALL
-    SELECT file_id, false AS is_new
-    FROM files
-    WHERE file_hash = $1
-    LIMIT 1;
+                    INSERT INTO files (file_hash, file_size)
+                    VALUES ($1, $2)
+                    ON CONFLICT (file_hash) DO UPDATE SET file_deleted = FALSE, created_at = NOW()
+                    WHERE files.file_deleted
+                    RETURNING file_id, true AS is_new
+                ),
+                touched AS (
+                    UPDATE files SET created_at = NOW()
+                    WHERE file_hash = $1 AND file_deleted = FALSE
+                    RETURNING file_id, false AS is_new
+                )
+                SELECT file_id, is_new
+                FROM ins
+                UNION ALL
+                SELECT file_id, is_new
+                FROM touched
+                LIMIT 1;
                 "#,
-                &file_hash,
+                file_hash,
                 file_size
             )
-            .fetch_one(&state.database.pool)
+            .fetch_optional(&state.database.pool)
             .await?;
-    
-        tracing::info!("File: {:?}", newly_created_file);
 
-        let file = AssetFile {
-            path: file_hash.to_string()
-        };
+            if let Some(newly_created_file) = newly_created_file {
+                tracing::info!("File: {:?}", newly_created_file);
 
-        Ok((file, newly_created_file, file_hash, content_type, file_size))
+                return Ok(newly_created_file);
+            }
+        }
+
+        Err(sqlx::Error::RowNotFound)
+    }
+}
+
+/// Amount of leading bytes kept in memory to sniff the content type of a spooled file
+const SNIFF_LENGTH: usize = 8192;
+
+/// A file that has been written to a temporary file on disk while being hashed
+///
+/// Used to ingest large files without holding them in memory
+#[derive(Debug)]
+pub struct SpooledFile {
+    pub file: async_std::fs::File,
+    pub file_hash: String,
+    pub file_size: i64,
+    /// The first `SNIFF_LENGTH` bytes of the file
+    pub head: Vec<u8>,
+}
+
+impl SpooledFile {
+    #[tracing::instrument(name = "spool", skip(reader))]
+    pub async fn spool(mut reader: impl AsyncRead + Unpin) -> std::io::Result<Self> {
+        let mut file = async_std::fs::File::from(tempfile::tempfile()?);
+        let mut hasher = Sha256::new();
+        let mut head = Vec::new();
+        let mut file_size = 0;
+        let mut buf = vec![0u8; 64 * 1024];
+
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+
+            let chunk = &buf[..read];
+            hasher.update(chunk);
+
+            if head.len() < SNIFF_LENGTH {
+                let take = (SNIFF_LENGTH - head.len()).min(read);
+                head.extend_from_slice(&chunk[..take]);
+            }
+
+            file.write_all(chunk).await?;
+            file_size += read as i64;
+        }
+
+        file.flush().await?;
+        file.seek(SeekFrom::Start(0)).await?;
+
+        Ok(Self {
+            file,
+            file_hash: format!("{:x}", hasher.finalize()),
+            file_size,
+            head,
+        })
     }
 }
 
+fn content_type(head: &[u8], path: &str) -> String {
+    infer::get(head)
+        .map(|t| t.mime_type().to_string()).unwrap_or_else(|| {
+            content_type_from_file_name(path)
+        })
+}
+
 #[tracing::instrument(name = "hash_file", skip(file))]
 fn hash_file(file: &[u8]) -> String {
     let mut hasher = Sha256::new();
@@ -71,15 +196,20 @@ fn hash_file(file: &[u8]) -> String {
     format!("{:x}", hash)
 }
 
-fn content_type_from_file_name(file_name: &str) -> String {
+pub fn content_type_from_file_name(file_name: &str) -> String {
     let extension = file_name.split('.').last().unwrap_or_default();
 
     info!("Content type from file name: {:?}", extension);
 
     match extension {
-        "js" => "text/javascript".to_string(),
+        "html" | "htm" => "text/html".to_string(),
+        "js" | "mjs" => "text/javascript".to_string(),
         "css" => "text/css".to_string(),
         "json" => "application/json".to_string(),
+        "svg" => "image/svg+xml".to_string(),
+        "txt" => "text/plain".to_string(),
+        "xml" => "application/xml".to_string(),
+        "wasm" => "application/wasm".to_string(),
         _ => {
             info!("Unknown file extension: {:?}", extension);
             "application/octet-stream".to_string()
@@ -0,0 +1,168 @@
+use std::io::SeekFrom;
+
+use async_compression::{
+    futures::bufread::{BrotliEncoder, GzipEncoder},
+    Level,
+};
+use chrono::{DateTime, Utc};
+use futures::{io::BufReader, AsyncSeekExt};
+use opentelemetry::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, query_scalar};
+use tracing::{info, info_span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{database::Database, state::State};
+
+use super::SpooledFile;
+
+/// Files smaller than this are not worth compressing
+const MIN_COMPRESS_SIZE: i64 = 1024;
+/// Files larger than this are served as is, compressing them would stall the upload
+const MAX_COMPRESS_SIZE: i64 = 32 * 1024 * 1024;
+
+/// Content codings variants are generated for, in order of preference
+pub const ENCODINGS: [&str; 2] = ["br", "gzip"];
+
+/// A precompressed copy of a file, served when the client accepts its `encoding`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileVariant {
+    pub file_id: i64,
+    /// `br` or `gzip`
+    pub encoding: String,
+    /// sha256 of the compressed contents
+    pub variant_hash: String,
+    pub variant_size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FileVariant {
+    /// Where the variant is stored, kept apart from original blobs so a variant never shares a key with one
+    pub fn object_key(&self) -> String {
+        format!("variants/{}", self.variant_hash)
+    }
+
+    pub async fn get_by_file_id(db: &Database, file_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("FileVariant::get_by_file_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            FileVariant,
+            "SELECT * FROM file_variants WHERE file_id = $1",
+            file_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// Compresses a freshly spooled file into the variants it doesn't have yet
+    ///
+    /// Variants that don't save at least a tenth of the size are not stored
+    #[tracing::instrument(name = "FileVariant::generate", skip(state, spooled))]
+    pub async fn generate(
+        state: &State,
+        file_id: i64,
+        spooled: &mut SpooledFile,
+        content_type: &str,
+    ) -> Result<Vec<Self>, color_eyre::eyre::Error> {
+        if !is_compressible(content_type)
+            || spooled.file_size < MIN_COMPRESS_SIZE
+            || spooled.file_size > MAX_COMPRESS_SIZE
+        {
+            return Ok(Vec::new());
+        }
+
+        let existing = query_scalar!(
+            "SELECT encoding FROM file_variants WHERE file_id = $1",
+            file_id
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        let mut variants = Vec::new();
+
+        for encoding in ENCODINGS {
+            if existing.iter().any(|existing| existing == encoding) {
+                continue;
+            }
+
+            let mut compressed = compress(spooled, encoding).await?;
+
+            if compressed.file_size * 10 > spooled.file_size * 9 {
+                info!("Skipping {} variant of file {}, it barely compresses", encoding, file_id);
+                continue;
+            }
+
+            state
+                .storage
+                .bucket
+                .put_object_stream_with_content_type(
+                    &mut compressed.file,
+                    format!("variants/{}", compressed.file_hash),
+                    content_type,
+                )
+                .await?;
+
+            let variant = query_as!(
+                FileVariant,
+                r#"
+                INSERT INTO file_variants (file_id, encoding, variant_hash, variant_size)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (file_id, encoding) DO UPDATE SET variant_hash = EXCLUDED.variant_hash
+                RETURNING *
+                "#,
+                file_id,
+                encoding,
+                compressed.file_hash,
+                compressed.file_size
+            )
+            .fetch_one(&state.database.pool)
+            .await?;
+
+            variants.push(variant);
+        }
+
+        Ok(variants)
+    }
+}
+
+/// Compresses the contents of a spooled file into a new spooled file
+async fn compress(spooled: &mut SpooledFile, encoding: &str) -> std::io::Result<SpooledFile> {
+    spooled.file.seek(SeekFrom::Start(0)).await?;
+
+    let reader = BufReader::new(&mut spooled.file);
+
+    match encoding {
+        "br" => SpooledFile::spool(BrotliEncoder::with_quality(reader, Level::Best)).await,
+        _ => SpooledFile::spool(GzipEncoder::with_quality(reader, Level::Best)).await,
+    }
+}
+
+/// Whether files of a mime type shrink when compressed, images, video and archives already are
+pub fn is_compressible(mime_type: &str) -> bool {
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+
+    mime_type.starts_with("text/")
+        || mime_type.ends_with("+json")
+        || mime_type.ends_with("+xml")
+        || matches!(
+            mime_type,
+            "application/javascript" | "application/json" | "application/xml" | "application/wasm"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("text/html"));
+        assert!(is_compressible("text/css; charset=utf-8"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(is_compressible("application/wasm"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+    }
+}
@@ -1,18 +1,143 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+
+use crate::{
+    models::{
+        access::SiteAccess,
+        deployment::{rules::DeploymentRules, Deployment, DeploymentFileEntry},
+        domain::Domain,
+    },
+    routes::serve::resolve::{CandidateKind, ServingOptions},
+    state::CacheConfig,
+};
+
+/// How long failed access attempts of a client are remembered, restarted by every attempt
+pub const ACCESS_FAILURES_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// What a `Host` header resolves to, the domain entry and the deployment it currently serves
+#[derive(Debug, Clone)]
+pub struct HostTarget {
+    pub domain: Domain,
+    pub deployment_id: Option<String>,
+}
+
+/// Everything needed to serve from a deployment apart from its files
+#[derive(Debug)]
+pub struct ServedDeployment {
+    pub deployment: Deployment,
+    pub options: ServingOptions,
+    pub rules: Option<DeploymentRules>,
+    /// The access controls of the site and its environments
+    pub access: Vec<SiteAccess>,
+}
+
+/// Deployment id and request path
+pub type FileKey = (String, String);
 
 #[derive(Debug)]
 pub struct Cache {
     // pub raw: DashMap<String, Shared<BoxFuture<'static, CachedValue<serde_json::Value>>>>,
     pub raw: moka::future::Cache<String, serde_json::Value>,
+    /// Resolved hosts, `None` for hosts that aren't a domain of any site
+    pub hosts: moka::future::Cache<String, Option<HostTarget>>,
+    /// Served deployments by deployment id
+    pub deployments: moka::future::Cache<String, Arc<ServedDeployment>>,
+    /// Request paths resolved to deployment files, `None` for paths without a file
+    pub files: moka::future::Cache<FileKey, Option<(DeploymentFileEntry, CandidateKind)>>,
+    /// Contents of small hot objects by object key, bounded by their total size in bytes
+    ///
+    /// Objects are content addressed so entries never go stale
+    pub content: moka::future::Cache<String, Bytes>,
+    /// Objects larger than this are always streamed from the bucket
+    pub content_max_object_size: u64,
+    /// Credentials that passed the access controls of a site, saves an argon2 verification per request
+    pub access_grants: moka::future::Cache<String, ()>,
+    /// Credential checks per site and client address since its last success
+    pub access_failures: moka::future::Cache<String, u32>,
 }
 
-impl Default for Cache {
-    fn default() -> Self {
+impl Cache {
+    pub fn new(config: Option<&CacheConfig>) -> Self {
+        let content_max_bytes = config
+            .map(|config| config.content_max_bytes)
+            .unwrap_or_else(CacheConfig::default_content_max_bytes);
+        let content_max_object_size = config
+            .map(|config| config.content_max_object_size)
+            .unwrap_or_else(CacheConfig::default_content_max_object_size);
+        // invalidation only reaches this process, the ttl bounds staleness when running several engines
+        let resolution_ttl = Duration::from_secs(
+            config
+                .map(|config| config.resolution_ttl_seconds)
+                .unwrap_or_else(CacheConfig::default_resolution_ttl_seconds),
+        );
+
         Self {
             raw: moka::future::Cache::builder()
                 .max_capacity(1000)
                 .time_to_live(Duration::from_secs(10))
                 .build(),
+            hosts: moka::future::Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(resolution_ttl)
+                .build(),
+            deployments: moka::future::Cache::builder()
+                .max_capacity(1000)
+                .time_to_live(resolution_ttl)
+                .support_invalidation_closures()
+                .build(),
+            files: moka::future::Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(resolution_ttl)
+                .support_invalidation_closures()
+                .build(),
+            content: moka::future::Cache::builder()
+                .max_capacity(content_max_bytes)
+                .weigher(|_key: &String, value: &Bytes| value.len().try_into().unwrap_or(u32::MAX))
+                .build(),
+            content_max_object_size,
+            access_grants: moka::future::Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(600))
+                .build(),
+            access_failures: moka::future::Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(ACCESS_FAILURES_WINDOW)
+                .build(),
         }
     }
+
+    /// Forgets which deployment every host serves, called when domains, environments or the live deployment change
+    pub fn invalidate_hosts(&self) {
+        self.hosts.invalidate_all();
+    }
+
+    /// Forgets the files and rules of a deployment, called when files are added to it or it is removed
+    pub async fn invalidate_deployment(&self, deployment_id: &str) {
+        self.deployments.invalidate(deployment_id).await;
+
+        let deployment_id = deployment_id.to_string();
+        // closures are enabled on the builder
+        let _ = self
+            .files
+            .invalidate_entries_if(move |(id, _), _| *id == deployment_id);
+    }
+
+    /// Forgets everything resolved for a site, called when its serving settings or access controls change
+    pub fn invalidate_site(&self, site_id: &str) {
+        self.invalidate_hosts();
+
+        let site_id = site_id.to_string();
+        let _ = self
+            .deployments
+            .invalidate_entries_if(move |_, served| served.deployment.site_id == site_id);
+        // candidate paths depend on the serving settings
+        self.files.invalidate_all();
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(None)
+    }
 }
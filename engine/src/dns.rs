@@ -0,0 +1,134 @@
+use std::net::{IpAddr, SocketAddr};
+
+use color_eyre::eyre::{eyre, Result};
+use hickory_resolver::{
+    config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::rr::{RData, RecordType},
+    system_conf::read_system_conf,
+    TokioAsyncResolver,
+};
+
+use crate::state::DnsConfig;
+
+/// Looks up the records domains are verified with
+///
+/// Queries the configured nameservers, or the ones of the system when none are set
+#[derive(Debug)]
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl DnsResolver {
+    pub fn from_config(config: Option<&DnsConfig>) -> Result<Self> {
+        let nameservers = match config.and_then(|config| config.nameservers.as_deref()) {
+            Some(nameservers) => parse_nameservers(nameservers)?,
+            None => Vec::new(),
+        };
+
+        let mut options = ResolverOpts::default();
+        // record changes have to show up on the next check
+        options.cache_size = 0;
+
+        let resolver_config = if nameservers.is_empty() {
+            read_system_conf()?.0
+        } else {
+            let group: Vec<NameServerConfig> = nameservers
+                .into_iter()
+                .flat_map(|address| {
+                    [
+                        NameServerConfig::new(address, Protocol::Udp),
+                        NameServerConfig::new(address, Protocol::Tcp),
+                    ]
+                })
+                .collect();
+
+            ResolverConfig::from_parts(None, vec![], group)
+        };
+
+        Ok(Self {
+            resolver: TokioAsyncResolver::tokio(resolver_config, options),
+        })
+    }
+
+    /// The TXT records at `name`, empty when the name doesn't exist or has none
+    ///
+    /// Records split into several strings are joined back together
+    pub async fn txt_records(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        match self.resolver.txt_lookup(name).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|part| String::from_utf8_lossy(part))
+                        .collect::<String>()
+                })
+                .collect()),
+            Err(error) if is_no_records(&error) => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The names `name` is an alias of, lowercased and without the trailing dot
+    pub async fn cname_targets(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        match self.resolver.lookup(name, RecordType::CNAME).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .filter_map(|record| match record {
+                    RData::CNAME(cname) => Some(cname.0.to_utf8().trim_end_matches('.').to_lowercase()),
+                    _ => None,
+                })
+                .collect()),
+            Err(error) if is_no_records(&error) => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// The A and AAAA records of `name`, following aliases
+    pub async fn ip_addresses(&self, name: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        match self.resolver.lookup_ip(name).await {
+            Ok(lookup) => Ok(lookup.iter().collect()),
+            Err(error) if is_no_records(&error) => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+fn is_no_records(error: &ResolveError) -> bool {
+    matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+/// Parses a comma separated list of nameservers, `1.1.1.1`, `127.0.0.1:5353` or `[::1]:53`
+fn parse_nameservers(nameservers: &str) -> Result<Vec<SocketAddr>> {
+    nameservers
+        .split(',')
+        .map(str::trim)
+        .filter(|nameserver| !nameserver.is_empty())
+        .map(|nameserver| {
+            nameserver
+                .parse::<SocketAddr>()
+                .or_else(|_| nameserver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| eyre!("Invalid nameserver: {:?}", nameserver))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nameservers() {
+        assert_eq!(
+            parse_nameservers("1.1.1.1, 127.0.0.1:5353,[::1]:53").unwrap(),
+            vec![
+                "1.1.1.1:53".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:5353".parse().unwrap(),
+                "[::1]:53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_nameservers("").unwrap().is_empty());
+        assert!(parse_nameservers("dns.google").is_err());
+    }
+}
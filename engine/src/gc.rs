@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as};
+use tracing::{error, info, warn};
+
+use crate::{
+    assets::variants::FileVariant,
+    models::deployment::retention::DeploymentRetention,
+    state::{AppState, GcConfig, State},
+};
+
+/// A blob that is no longer referenced by any deployment
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct GcFile {
+    pub file_id: i64,
+    pub file_hash: String,
+    pub file_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Object)]
+pub struct GcReport {
+    /// Nothing was deleted, the report lists what would be
+    pub dry_run: bool,
+    pub files: Vec<GcFile>,
+    /// Total size of the collected files in bytes
+    pub bytes: i64,
+}
+
+/// Enforces site retention policies and, when configured, collects garbage every `GC_INTERVAL_HOURS`
+pub async fn run_scheduled(state: State) {
+    if state.config.gc.is_none() {
+        info!("Garbage collection is disabled, set a GC_ variable (e.g. GC_INTERVAL_HOURS) to enable it");
+    }
+
+    let interval_hours = state
+        .config
+        .gc
+        .as_ref()
+        .map(|config| config.interval_hours)
+        .unwrap_or(24);
+
+    loop {
+        if let Err(error) = DeploymentRetention::enforce(&state).await {
+            error!("Enforcing retention policies failed: {:?}", error);
+        }
+
+        if let Some(config) = &state.config.gc {
+            match collect(&state, config, config.dry_run).await {
+                Ok(report) => info!(
+                    "Garbage collection {}: {} files ({} bytes)",
+                    if report.dry_run { "dry run" } else { "complete" },
+                    report.files.len(),
+                    report.bytes
+                ),
+                Err(error) => error!("Garbage collection failed: {:?}", error),
+            }
+        }
+
+        async_std::task::sleep(Duration::from_secs(interval_hours * 60 * 60)).await;
+    }
+}
+
+/// Finds (and unless `dry_run` deletes) the blobs no deployment references anymore
+///
+/// Deployments themselves are never removed here, that is up to the retention policies of their sites (`DeploymentRetention`).
+/// Blobs referenced by a pending manifest, or created within the grace period, are kept.
+#[tracing::instrument(name = "gc::collect", skip(state, config))]
+pub async fn collect(state: &AppState, config: &GcConfig, dry_run: bool) -> Result<GcReport, sqlx::Error> {
+    let grace_cutoff = Utc::now() - chrono::Duration::hours(config.grace_period_hours);
+
+    let files = query_as!(
+        GcFile,
+        r#"
+        SELECT f.file_id, f.file_hash, f.file_size
+        FROM files f
+        WHERE f.file_deleted = FALSE
+        AND f.created_at < $1
+        AND NOT EXISTS (SELECT 1 FROM deployment_files df WHERE df.file_id = f.file_id)
+        AND NOT EXISTS (
+            SELECT 1 FROM deployment_manifest_files m WHERE m.file_hash = f.file_hash
+        )
+        "#,
+        grace_cutoff
+    )
+    .fetch_all(&state.database.pool)
+    .await?;
+
+    let mut report = GcReport {
+        dry_run,
+        bytes: files.iter().filter_map(|file| file.file_size).sum(),
+        files,
+    };
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    let file_ids: Vec<i64> = report.files.iter().map(|file| file.file_id).collect();
+
+    report.files = delete_files(state, &file_ids, grace_cutoff).await?;
+    report.bytes = report.files.iter().filter_map(|file| file.file_size).sum();
+
+    if !report.files.is_empty() {
+        // resolved paths may still point at the removed blobs
+        state.cache.files.invalidate_all();
+    }
+
+    Ok(report)
+}
+
+/// Marks the files as deleted and removes their blobs and compressed variants from the bucket
+///
+/// Files still referenced by any deployment or pending manifest are skipped, as are files (re)used since `grace_cutoff`.
+/// Every file is marked in its own transaction that holds the row until its blobs are gone, uploads reusing it wait and upload it again
+pub async fn delete_files(
+    state: &AppState,
+    file_ids: &[i64],
+    grace_cutoff: DateTime<Utc>,
+) -> Result<Vec<GcFile>, sqlx::Error> {
+    let mut deleted = Vec::with_capacity(file_ids.len());
+
+    for file_id in file_ids {
+        let mut tx = state.database.pool.begin().await?;
+
+        let marked = query_as!(
+            GcFile,
+            r#"
+            UPDATE files f SET file_deleted = TRUE
+            WHERE f.file_id = $1
+            AND f.file_deleted = FALSE
+            AND f.created_at < $2
+            AND NOT EXISTS (SELECT 1 FROM deployment_files df WHERE df.file_id = f.file_id)
+            AND NOT EXISTS (
+                SELECT 1 FROM deployment_manifest_files m WHERE m.file_hash = f.file_hash
+            )
+            RETURNING f.file_id, f.file_hash, f.file_size
+            "#,
+            file_id,
+            grace_cutoff
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(marked) = marked else {
+            continue;
+        };
+
+        match delete_blobs(state, &marked).await {
+            Ok(()) => {
+                tx.commit().await?;
+                deleted.push(marked);
+            }
+            Err(error) => {
+                // rolled back so the next run retries instead of leaving an orphaned object behind
+                warn!("Failed to delete blob {}: {:?}", marked.file_hash, error);
+            }
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Removes the blob of a file together with its compressed variants
+async fn delete_blobs(state: &AppState, file: &GcFile) -> Result<(), color_eyre::eyre::Error> {
+    let variants = FileVariant::get_by_file_id(&state.database, file.file_id).await?;
+
+    for variant in &variants {
+        state.storage.bucket.delete_object(variant.object_key()).await?;
+    }
+
+    query!("DELETE FROM file_variants WHERE file_id = $1", file.file_id)
+        .execute(&state.database.pool)
+        .await?;
+
+    state.storage.bucket.delete_object(&file.file_hash).await?;
+
+    Ok(())
+}
+
+/// Removes the car archive, car file and preview images of a deployment from their buckets
+///
+/// Returns false when an object could not be deleted
+pub async fn delete_artifacts(state: &AppState, deployment_id: &str, preview_paths: &[String]) -> bool {
+    if let Some(car_bucket) = &state.storage.car_bucket {
+        for path in [
+            format!("{}/car.zip", deployment_id),
+            format!("{}/deploy.car", deployment_id),
+        ] {
+            if let Err(error) = car_bucket.delete_object(&path).await {
+                warn!("Failed to delete car artifact {}: {:?}", path, error);
+                return false;
+            }
+        }
+    }
+
+    if let Some(previews_bucket) = &state.storage.previews_bucket {
+        for path in preview_paths {
+            if let Err(error) = previews_bucket.delete_object(path).await {
+                warn!("Failed to delete preview artifact {}: {:?}", path, error);
+                return false;
+            }
+        }
+    }
+
+    true
+}
@@ -103,12 +103,22 @@ impl CarHandler {
 
             tracing::info!("Received car response: {:?}", payload);
 
-            if let Some(ipfs_cid) = payload.cid {
-                Deployment::update_ipfs_cid(&state.database, &payload.deployment_id, &ipfs_cid)
+            if let Some(ipfs_cid) = &payload.cid {
+                Deployment::update_ipfs_cid(&state.database, &payload.deployment_id, ipfs_cid)
                     .await
                     .ok(); 
             }
 
+            let ipfs_status = if payload.success && payload.cid.is_some() {
+                "ready"
+            } else {
+                "failed"
+            };
+
+            Deployment::set_ipfs_status(state, &payload.deployment_id, ipfs_status, payload.error.clone())
+                .await
+                .ok();
+
             // if let Some(file_path) = payload.file_path {
             //     // todo pin file using ipfs-cluster
 
@@ -9,3 +9,19 @@ impl IPFSModule {
         Self { cluster_url, public_cluster_url }
     }
 }
+
+impl IPFSModule {
+    /// Removes a pin from the cluster, pins that don't exist (anymore) are ignored
+    pub async fn unpin(&self, cid: &str) -> Result<(), reqwest::Error> {
+        let response = reqwest::Client::new()
+            .delete(format!("{}/pins/{}", self.cluster_url.trim_end_matches('/'), cid))
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+
+        response.error_for_status().map(|_| ())
+    }
+}
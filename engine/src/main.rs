@@ -10,15 +10,20 @@ use opentelemetry_sdk::Resource;
 use state::AppState;
 use tracing::{error, info};
 
+pub mod acme;
 pub mod assets;
 pub mod cache;
 pub mod database;
+pub mod dns;
+pub mod gc;
 pub mod middlewares;
 pub mod models;
 pub mod routes;
 pub mod state;
 pub mod storage;
+pub mod tls;
 pub mod utils;
+pub mod verifier;
 pub mod handlers;
 pub mod ipfs;
 
@@ -96,6 +101,10 @@ async fn main() {
 
     let app_state = Arc::new(state);
 
+    async_std::task::spawn(gc::run_scheduled(app_state.clone()));
+    async_std::task::spawn(verifier::run_scheduled(app_state.clone()));
+    async_std::task::spawn(acme::run_scheduled(app_state.clone()));
+
     if let Some(rabbit) = &app_state.clone().rabbit {
         rabbit.do_consume(&app_state.clone()).join(routes::serve(app_state)).await;
     } else {
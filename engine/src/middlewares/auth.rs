@@ -10,7 +10,7 @@ use tracing::{info, info_span, Instrument};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
-    models::{keys::Key, session::Session, team::Team},
+    models::{keys::Key, session::Session, team::Team, user::User},
     routes::error::HttpError,
     state::State,
     utils::hash::hash_session,
@@ -207,6 +207,23 @@ impl UserAuth {
         .await
     }
 
+    /// Requires the request to be made by an instance admin
+    pub async fn required_admin(&self) -> Result<(), HttpError> {
+        match self {
+            UserAuth::User(session, state) => {
+                let user = User::get_by_id(&state.database, &session.user_id).await?;
+
+                if user.admin.unwrap_or(false) {
+                    Ok(())
+                } else {
+                    Err(HttpError::Forbidden)
+                }
+            }
+            UserAuth::Key(_, _) => Err(HttpError::Forbidden),
+            UserAuth::None(_) => Err(HttpError::Unauthorized),
+        }
+    }
+
     pub async fn verify_access_to(
         &self,
         resource: &impl AccessibleResource,
@@ -0,0 +1,248 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use ipnetwork::IpNetwork;
+use opentelemetry::Context;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{query, query_as};
+use tracing::info_span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{database::Database, utils::hash::verify_access_password};
+
+/// Access controls of a site or of one of its environments
+///
+/// Requests have to come from one of `allowed_ips` (when set) and pass basic auth or the password page (when set)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteAccess {
+    pub site_id: String,
+    /// `None` protects every domain and preview of the site, otherwise only the domains bound to the environment
+    pub environment: Option<String>,
+    pub basic_auth_username: Option<String>,
+    /// argon2 PHC string
+    pub basic_auth_password_hash: Option<String>,
+    /// argon2 PHC string of the password asked for by the password page
+    pub password_hash: Option<String>,
+    /// Key the cookies of the password page are signed with, regenerated on every change
+    pub cookie_secret: String,
+    pub allowed_ips: Vec<IpNetwork>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The access controls of a site as shown by the api, without credentials
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SiteAccessInfo {
+    pub site_id: String,
+    pub environment: Option<String>,
+    pub basic_auth_username: Option<String>,
+    pub password_protected: bool,
+    pub allowed_ips: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<SiteAccess> for SiteAccessInfo {
+    fn from(access: SiteAccess) -> Self {
+        Self {
+            site_id: access.site_id,
+            environment: access.environment,
+            basic_auth_username: access.basic_auth_username,
+            password_protected: access.password_hash.is_some(),
+            allowed_ips: access.allowed_ips.iter().map(ToString::to_string).collect(),
+            created_at: access.created_at,
+            updated_at: access.updated_at,
+        }
+    }
+}
+
+impl SiteAccess {
+    pub async fn get_by_site_id(db: &Database, site_id: &str) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("SiteAccess::get_by_site_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            SiteAccess,
+            "SELECT * FROM site_access WHERE site_id = $1 ORDER BY environment NULLS FIRST",
+            site_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// Replaces the access controls of a site or environment, signing out everyone who passed the password page
+    pub async fn set(
+        db: &Database,
+        site_id: &str,
+        environment: Option<&str>,
+        basic_auth: Option<(&str, &str)>,
+        password_hash: Option<&str>,
+        allowed_ips: &[IpNetwork],
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("SiteAccess::set");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let cookie_secret = hex::encode(rand::random::<[u8; 32]>());
+
+        query_as!(
+            SiteAccess,
+            r#"
+            INSERT INTO site_access (
+                site_id, environment, basic_auth_username, basic_auth_password_hash,
+                password_hash, cookie_secret, allowed_ips
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (site_id, (COALESCE(environment, ''))) DO UPDATE
+            SET basic_auth_username = EXCLUDED.basic_auth_username,
+                basic_auth_password_hash = EXCLUDED.basic_auth_password_hash,
+                password_hash = EXCLUDED.password_hash,
+                cookie_secret = EXCLUDED.cookie_secret,
+                allowed_ips = EXCLUDED.allowed_ips,
+                updated_at = NOW()
+            RETURNING *
+            "#,
+            site_id,
+            environment,
+            basic_auth.map(|(username, _)| username),
+            basic_auth.map(|(_, hash)| hash),
+            password_hash,
+            cookie_secret,
+            allowed_ips
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    pub async fn delete(db: &Database, site_id: &str, environment: Option<&str>) -> Result<bool, sqlx::Error> {
+        let span = info_span!("SiteAccess::delete");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let result = query!(
+            "DELETE FROM site_access WHERE site_id = $1 AND environment IS NOT DISTINCT FROM $2",
+            site_id,
+            environment
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The access controls for a host bound to `environment`, the environment's own or else the site wide ones
+    pub fn for_environment<'a>(rules: &'a [SiteAccess], environment: Option<&str>) -> Option<&'a SiteAccess> {
+        environment
+            .and_then(|environment| {
+                rules
+                    .iter()
+                    .find(|rule| rule.environment.as_deref() == Some(environment))
+            })
+            .or_else(|| rules.iter().find(|rule| rule.environment.is_none()))
+    }
+
+    pub fn allows_ip(&self, ip: IpAddr) -> bool {
+        self.allowed_ips.is_empty() || self.allowed_ips.iter().any(|network| network.contains(ip))
+    }
+
+    pub fn verify_basic_auth(&self, username: &str, password: &str) -> bool {
+        match (&self.basic_auth_username, &self.basic_auth_password_hash) {
+            (Some(expected), Some(hash)) => expected == username && verify_access_password(password, hash),
+            _ => false,
+        }
+    }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.password_hash
+            .as_deref()
+            .is_some_and(|hash| verify_access_password(password, hash))
+    }
+
+    /// A cookie value proving the password page was passed, valid until `expires` (unix seconds)
+    pub fn sign_cookie(&self, expires: i64) -> String {
+        format!("{}.{}", expires, hex::encode(self.cookie_mac(expires).finalize().into_bytes()))
+    }
+
+    pub fn verify_cookie(&self, value: &str, now: i64) -> bool {
+        let Some((expires, signature)) = value.split_once('.') else {
+            return false;
+        };
+
+        let (Ok(expires), Ok(signature)) = (expires.parse::<i64>(), hex::decode(signature)) else {
+            return false;
+        };
+
+        expires > now && self.cookie_mac(expires).verify_slice(&signature).is_ok()
+    }
+
+    fn cookie_mac(&self, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.cookie_secret.as_bytes())
+            .expect("hmac accepts keys of any length");
+
+        mac.update(self.site_id.as_bytes());
+        mac.update(b":");
+        mac.update(self.environment.as_deref().unwrap_or_default().as_bytes());
+        mac.update(b":");
+        mac.update(expires.to_string().as_bytes());
+
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(environment: Option<&str>, allowed_ips: &[&str]) -> SiteAccess {
+        SiteAccess {
+            site_id: "s_1234567890".to_string(),
+            environment: environment.map(ToString::to_string),
+            basic_auth_username: None,
+            basic_auth_password_hash: None,
+            password_hash: None,
+            cookie_secret: "secret".to_string(),
+            allowed_ips: allowed_ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_for_environment() {
+        let rules = vec![access(None, &[]), access(Some("staging"), &[])];
+
+        let rule = |environment| SiteAccess::for_environment(&rules, environment).map(|rule| rule.environment.clone());
+
+        assert_eq!(rule(None), Some(None));
+        assert_eq!(rule(Some("staging")), Some(Some("staging".to_string())));
+        assert_eq!(rule(Some("pr-1")), Some(None));
+        assert!(SiteAccess::for_environment(&rules[1..], None).is_none());
+    }
+
+    #[test]
+    fn test_allows_ip() {
+        assert!(access(None, &[]).allows_ip("203.0.113.7".parse().unwrap()));
+
+        let rule = access(None, &["10.0.0.0/8", "2001:db8::/32"]);
+
+        assert!(rule.allows_ip("10.1.2.3".parse().unwrap()));
+        assert!(rule.allows_ip("2001:db8::1".parse().unwrap()));
+        assert!(!rule.allows_ip("192.168.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cookie() {
+        let rule = access(None, &[]);
+        let cookie = rule.sign_cookie(2000);
+
+        assert!(rule.verify_cookie(&cookie, 1000));
+        assert!(!rule.verify_cookie(&cookie, 3000));
+        assert!(!rule.verify_cookie(&cookie.replace("2000.", "9000."), 1000));
+        assert!(!access(Some("staging"), &[]).verify_cookie(&cookie, 1000));
+        assert!(!rule.verify_cookie("garbage", 1000));
+    }
+}
@@ -0,0 +1,237 @@
+use chrono::{DateTime, Utc};
+use opentelemetry::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar};
+use tracing::info_span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::database::Database;
+
+/// The certificate of a verified domain, issued and renewed by the ACME worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Certificate {
+    pub domain: String,
+    /// PEM chain, `None` until the first issuance succeeded
+    pub certificate_pem: Option<String>,
+    /// PEM private key, encrypted with `ACME_ENCRYPTION_KEY`
+    pub key_encrypted: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// Why the last issuance failed, a previously issued certificate keeps being served
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Certificate {
+    pub async fn get_issued(db: &Database) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("Certificate::get_issued");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Certificate,
+            "SELECT * FROM certificates WHERE certificate_pem IS NOT NULL AND key_encrypted IS NOT NULL"
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// Verified domains without a certificate or whose certificate expires before `renew_cutoff`
+    ///
+    /// Domains flagged by the verifier are skipped, as are domains whose last attempt was after `retry_cutoff` and wildcards unless `include_wildcards`
+    pub async fn due(
+        db: &Database,
+        renew_cutoff: DateTime<Utc>,
+        retry_cutoff: DateTime<Utc>,
+        include_wildcards: bool,
+        limit: i64,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let span = info_span!("Certificate::due");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_scalar!(
+            r#"
+            SELECT d.domain
+            FROM domains d
+            LEFT JOIN certificates c ON c.domain = d.domain
+            WHERE d.failure_reason IS NULL
+            AND (c.expires_at IS NULL OR c.expires_at < $1)
+            AND (c.last_attempt_at IS NULL OR c.last_attempt_at < $2)
+            AND ($3 OR d.domain NOT LIKE '*.%')
+            ORDER BY c.expires_at NULLS FIRST
+            LIMIT $4
+            "#,
+            renew_cutoff,
+            retry_cutoff,
+            include_wildcards,
+            limit
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    pub async fn store(
+        db: &Database,
+        domain: &str,
+        certificate_pem: &str,
+        key_encrypted: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("Certificate::store");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Certificate,
+            r#"
+            INSERT INTO certificates (domain, certificate_pem, key_encrypted, expires_at, last_attempt_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT (domain) DO UPDATE
+            SET certificate_pem = EXCLUDED.certificate_pem,
+                key_encrypted = EXCLUDED.key_encrypted,
+                expires_at = EXCLUDED.expires_at,
+                last_attempt_at = NOW(),
+                failure_reason = NULL,
+                updated_at = NOW()
+            RETURNING *
+            "#,
+            domain,
+            certificate_pem,
+            key_encrypted,
+            expires_at
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    pub async fn record_failure(db: &Database, domain: &str, failure_reason: &str) -> Result<(), sqlx::Error> {
+        let span = info_span!("Certificate::record_failure");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!(
+            r#"
+            INSERT INTO certificates (domain, last_attempt_at, failure_reason)
+            VALUES ($1, NOW(), $2)
+            ON CONFLICT (domain) DO UPDATE
+            SET last_attempt_at = NOW(), failure_reason = EXCLUDED.failure_reason, updated_at = NOW()
+            "#,
+            domain,
+            failure_reason
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// The account certificates are ordered with at an ACME directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeAccount {
+    pub directory_url: String,
+    /// The `kid` requests are signed with
+    pub account_url: String,
+    /// PKCS#8 account key, encrypted with `ACME_ENCRYPTION_KEY`
+    pub key_encrypted: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AcmeAccount {
+    pub async fn get(db: &Database, directory_url: &str) -> Result<Option<Self>, sqlx::Error> {
+        let span = info_span!("AcmeAccount::get");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            AcmeAccount,
+            "SELECT * FROM acme_accounts WHERE directory_url = $1",
+            directory_url
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    pub async fn create(
+        db: &Database,
+        directory_url: &str,
+        account_url: &str,
+        key_encrypted: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("AcmeAccount::create");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            AcmeAccount,
+            r#"
+            INSERT INTO acme_accounts (directory_url, account_url, key_encrypted)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (directory_url) DO UPDATE
+            SET account_url = EXCLUDED.account_url, key_encrypted = EXCLUDED.key_encrypted
+            RETURNING *
+            "#,
+            directory_url,
+            account_url,
+            key_encrypted
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+}
+
+/// A key authorization answered at `/.well-known/acme-challenge/<token>` while an HTTP-01 challenge is validated
+///
+/// Kept in the database so any engine behind the same address can answer it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeHttpChallenge {
+    pub token: String,
+    pub key_authorization: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AcmeHttpChallenge {
+    pub async fn get(db: &Database, token: &str) -> Result<Option<Self>, sqlx::Error> {
+        let span = info_span!("AcmeHttpChallenge::get");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            AcmeHttpChallenge,
+            "SELECT * FROM acme_http_challenges WHERE token = $1",
+            token
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    pub async fn create(db: &Database, token: &str, key_authorization: &str) -> Result<(), sqlx::Error> {
+        let span = info_span!("AcmeHttpChallenge::create");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!(
+            "INSERT INTO acme_http_challenges (token, key_authorization) VALUES ($1, $2) ON CONFLICT (token) DO NOTHING",
+            token,
+            key_authorization
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(db: &Database, token: &str) -> Result<(), sqlx::Error> {
+        let span = info_span!("AcmeHttpChallenge::delete");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!("DELETE FROM acme_http_challenges WHERE token = $1", token)
+            .execute(&db.pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::SeekFrom;
+
+use futures::{AsyncReadExt, AsyncSeekExt};
+
+/// The archive formats accepted as deployment uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Detects the archive format from the leading bytes of an upload
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if head.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZst)
+        } else if head.len() >= 262 && &head[257..262] == b"ustar" {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the first block of a file to detect its format, the file is rewound afterwards
+    pub async fn sniff(file: &mut async_std::fs::File) -> std::io::Result<Option<Self>> {
+        let mut head = vec![0u8; 512];
+        let mut filled = 0;
+
+        while filled < head.len() {
+            let read = file.read(&mut head[filled..]).await?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+
+        file.seek(SeekFrom::Start(0)).await?;
+
+        Ok(ArchiveFormat::detect(&head[..filled]))
+    }
+}
+
+/// An archive entry whose path can't be stored, the upload is refused rather than served without it
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid path in archive: {0:?}")]
+pub struct InvalidEntryPath(pub String);
+
+/// Normalizes a path from an archive entry (or multipart file name) into a `deployment_files.file_path`
+///
+/// `./dist/index.html` becomes `dist/index.html`, paths escaping the root (`../x`) or without any file name are rejected
+pub fn normalize_entry_path(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ArchiveFormat::detect(b"PK\x03\x04rest"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::detect(&[0x1f, 0x8b, 0x08]), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Some(ArchiveFormat::TarZst));
+
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(ArchiveFormat::detect(&tar), Some(ArchiveFormat::Tar));
+
+        assert_eq!(ArchiveFormat::detect(b"<html>"), None);
+    }
+
+    #[test]
+    fn test_normalize_entry_path() {
+        assert_eq!(normalize_entry_path("./dist/index.html"), Some("dist/index.html".to_string()));
+        assert_eq!(normalize_entry_path("/index.html"), Some("index.html".to_string()));
+        assert_eq!(normalize_entry_path("assets\\app.js"), Some("assets/app.js".to_string()));
+        assert_eq!(normalize_entry_path("../etc/passwd"), None);
+        assert_eq!(normalize_entry_path("./"), None);
+    }
+}
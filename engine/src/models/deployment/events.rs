@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use async_std::channel::{bounded, Receiver, Sender, TrySendError};
+use dashmap::DashMap;
+use poem_openapi::{Enum, Object, Union};
+use serde::{Deserialize, Serialize};
+
+use super::Deployment;
+
+/// Lifecycle of a deployment
+///
+/// `created` -> `uploading` -> `processing` -> `ready`, any step may end in `failed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentStatus {
+    Created,
+    Uploading,
+    Processing,
+    Ready,
+    Failed,
+}
+
+impl DeploymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentStatus::Created => "created",
+            DeploymentStatus::Uploading => "uploading",
+            DeploymentStatus::Processing => "processing",
+            DeploymentStatus::Ready => "ready",
+            DeploymentStatus::Failed => "failed",
+        }
+    }
+
+    /// Parses the `deployments.status` column, unknown values are treated as `ready`
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "created" => DeploymentStatus::Created,
+            "uploading" => DeploymentStatus::Uploading,
+            "processing" => DeploymentStatus::Processing,
+            "failed" => DeploymentStatus::Failed,
+            _ => DeploymentStatus::Ready,
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, DeploymentStatus::Ready | DeploymentStatus::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DeploymentStatusEvent {
+    pub status: DeploymentStatus,
+    /// Reason the deployment failed
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DeploymentFileEvent {
+    pub path: String,
+    pub file_hash: String,
+    /// False when the file was already stored by an earlier deployment
+    pub uploaded: bool,
+    pub processed: u64,
+    /// Unknown for streamed tar archives
+    pub total: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DeploymentIpfsEvent {
+    /// `pending`, `ready` or `failed`
+    pub status: String,
+    pub cid: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DeploymentPreviewEvent {
+    /// `queued` or `ready`
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "type")]
+pub enum DeploymentEvent {
+    Status(DeploymentStatusEvent),
+    File(DeploymentFileEvent),
+    Ipfs(DeploymentIpfsEvent),
+    Preview(DeploymentPreviewEvent),
+}
+
+impl DeploymentEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            DeploymentEvent::Status(_) => "status",
+            DeploymentEvent::File(_) => "file",
+            DeploymentEvent::Ipfs(_) => "ipfs",
+            DeploymentEvent::Preview(_) => "preview",
+        }
+    }
+}
+
+/// What a subscriber has been told about a deployment, its status and the IPFS pin and preview that follow it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeploymentProgress {
+    pub status: Option<DeploymentStatus>,
+    pub ipfs_status: Option<String>,
+    pub preview_status: Option<String>,
+}
+
+impl DeploymentProgress {
+    /// Records a published event, returns false when it repeats what is already known
+    pub fn apply(&mut self, event: &DeploymentEvent) -> bool {
+        match event {
+            DeploymentEvent::Status(event) => replace(&mut self.status, event.status),
+            DeploymentEvent::Ipfs(event) => replace(&mut self.ipfs_status, event.status.clone()),
+            DeploymentEvent::Preview(event) => replace(&mut self.preview_status, event.status.clone()),
+            DeploymentEvent::File(_) => true,
+        }
+    }
+
+    /// Catches up with the stored deployment, returns the events for whatever changed
+    pub fn sync(&mut self, deployment: &Deployment) -> Vec<DeploymentEvent> {
+        let mut events = Vec::new();
+        let status = DeploymentStatus::parse(&deployment.status);
+
+        if replace(&mut self.status, status) {
+            events.push(DeploymentEvent::Status(DeploymentStatusEvent {
+                status,
+                message: deployment.status_message.clone(),
+            }));
+        }
+
+        if let Some(ipfs_status) = &deployment.ipfs_status {
+            if replace(&mut self.ipfs_status, ipfs_status.clone()) {
+                events.push(DeploymentEvent::Ipfs(DeploymentIpfsEvent {
+                    status: ipfs_status.clone(),
+                    cid: deployment.ipfs_cid.clone(),
+                    message: None,
+                }));
+            }
+        }
+
+        if let Some(preview_status) = &deployment.preview_status {
+            if replace(&mut self.preview_status, preview_status.clone()) {
+                events.push(DeploymentEvent::Preview(DeploymentPreviewEvent {
+                    status: preview_status.clone(),
+                }));
+            }
+        }
+
+        events
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.status.is_some_and(|status| status.is_terminal())
+    }
+
+    /// The deployment is `ready` or `failed` and neither its IPFS pin nor its preview is still pending
+    pub fn is_settled(&self) -> bool {
+        self.is_terminal()
+            && self.ipfs_status.as_deref() != Some("pending")
+            && self.preview_status.as_deref() != Some("queued")
+    }
+}
+
+fn replace<T: PartialEq>(current: &mut Option<T>, value: T) -> bool {
+    if current.as_ref() == Some(&value) {
+        return false;
+    }
+
+    *current = Some(value);
+    true
+}
+
+type Subscribers = Arc<DashMap<String, Vec<Sender<DeploymentEvent>>>>;
+
+/// In-process fan out of deployment events to server-sent event subscribers
+#[derive(Debug, Default)]
+pub struct DeploymentEvents {
+    subscribers: Subscribers,
+}
+
+impl DeploymentEvents {
+    pub fn subscribe(&self, deployment_id: &str) -> DeploymentSubscription {
+        let (sender, receiver) = bounded(256);
+
+        self.subscribers
+            .entry(deployment_id.to_string())
+            .or_default()
+            .push(sender);
+
+        DeploymentSubscription {
+            deployment_id: deployment_id.to_string(),
+            receiver,
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    pub fn publish(&self, deployment_id: &str, event: DeploymentEvent) {
+        if let Some(mut subscribers) = self.subscribers.get_mut(deployment_id) {
+            // slow subscribers miss events rather than stalling the upload
+            subscribers.retain(|sender| {
+                !matches!(sender.try_send(event.clone()), Err(TrySendError::Closed(_)))
+            });
+        }
+
+        self.subscribers
+            .remove_if(deployment_id, |_, subscribers| subscribers.is_empty());
+    }
+}
+
+/// The events published for one deployment, unsubscribes when dropped
+pub struct DeploymentSubscription {
+    deployment_id: String,
+    receiver: Receiver<DeploymentEvent>,
+    subscribers: Subscribers,
+}
+
+impl DeploymentSubscription {
+    pub async fn recv(&self) -> Option<DeploymentEvent> {
+        self.receiver.recv().await.ok()
+    }
+}
+
+impl Drop for DeploymentSubscription {
+    fn drop(&mut self) {
+        self.receiver.close();
+
+        if let Some(mut subscribers) = self.subscribers.get_mut(&self.deployment_id) {
+            subscribers.retain(|sender| !sender.is_closed());
+        }
+
+        self.subscribers
+            .remove_if(&self.deployment_id, |_, subscribers| subscribers.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: DeploymentStatus) -> DeploymentEvent {
+        DeploymentEvent::Status(DeploymentStatusEvent { status, message: None })
+    }
+
+    fn preview(status: &str) -> DeploymentEvent {
+        DeploymentEvent::Preview(DeploymentPreviewEvent {
+            status: status.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_progress_settles_after_sub_states() {
+        let mut progress = DeploymentProgress::default();
+
+        assert!(progress.apply(&status(DeploymentStatus::Processing)));
+        assert!(progress.apply(&preview("queued")));
+        assert!(progress.apply(&status(DeploymentStatus::Ready)));
+        assert!(!progress.apply(&status(DeploymentStatus::Ready)));
+        assert!(progress.is_terminal());
+        assert!(!progress.is_settled());
+
+        assert!(progress.apply(&preview("ready")));
+        assert!(progress.is_settled());
+    }
+
+    #[test]
+    fn test_subscription_unsubscribes_on_drop() {
+        let events = DeploymentEvents::default();
+
+        let subscription = events.subscribe("d_1");
+        events.publish("d_1", preview("queued"));
+        assert!(events.subscribers.contains_key("d_1"));
+
+        drop(subscription);
+        assert!(!events.subscribers.contains_key("d_1"));
+    }
+}
@@ -0,0 +1,190 @@
+use opentelemetry::Context;
+use poem_openapi::{types::Example, Object};
+use serde::{Deserialize, Serialize};
+use tracing::info_span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{assets::content_type_from_file_name, database::Database};
+
+use super::archive::normalize_entry_path;
+
+/// A file a client intends to deploy, identified by the sha256 of its contents
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+#[oai(example)]
+pub struct ManifestEntry {
+    /// Path relative to the site root
+    pub path: String,
+    /// Lowercase hex sha256 of the file contents
+    pub sha256: String,
+    pub size: i64,
+    /// Defaults to a guess based on the file extension
+    pub mime_type: Option<String>,
+}
+
+impl Example for ManifestEntry {
+    fn example() -> Self {
+        Self {
+            path: "index.html".to_string(),
+            sha256: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824".to_string(),
+            size: 5,
+            mime_type: Some("text/html".to_string()),
+        }
+    }
+}
+
+impl ManifestEntry {
+    /// Validates and normalizes the entry, returning a human readable reason when it is rejected
+    pub fn validate(self) -> Result<Self, String> {
+        let path = normalize_entry_path(&self.path)
+            .ok_or_else(|| format!("Invalid path: {:?}", self.path))?;
+
+        let sha256 = self.sha256.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid sha256 for {:?}", path));
+        }
+
+        if self.size < 0 {
+            return Err(format!("Invalid size for {:?}", path));
+        }
+
+        let mime_type = self
+            .mime_type
+            .unwrap_or_else(|| content_type_from_file_name(&path));
+
+        Ok(Self {
+            path,
+            sha256,
+            size: self.size,
+            mime_type: Some(mime_type),
+        })
+    }
+}
+
+pub struct DeploymentManifest;
+
+impl DeploymentManifest {
+    /// Records the manifest entries for a deployment, replacing earlier entries for the same paths
+    pub async fn add_entries(
+        db: &Database,
+        deployment_id: &str,
+        entries: &[ManifestEntry],
+    ) -> Result<(), sqlx::Error> {
+        let span = info_span!("DeploymentManifest::add_entries");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let paths: Vec<String> = entries.iter().map(|x| x.path.clone()).collect();
+        let hashes: Vec<String> = entries.iter().map(|x| x.sha256.clone()).collect();
+        let sizes: Vec<i64> = entries.iter().map(|x| x.size).collect();
+        let mime_types: Vec<String> = entries
+            .iter()
+            .map(|x| x.mime_type.clone().unwrap_or_default())
+            .collect();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO deployment_manifest_files (deployment_id, file_path, file_hash, file_size, mime_type)
+            SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::bigint[], $5::text[])
+            ON CONFLICT (deployment_id, file_path) DO UPDATE
+            SET file_hash = EXCLUDED.file_hash, file_size = EXCLUDED.file_size, mime_type = EXCLUDED.mime_type
+            "#,
+            deployment_id,
+            &paths,
+            &hashes,
+            &sizes,
+            &mime_types
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Number of files in the manifest of a deployment
+    pub async fn count(db: &Database, deployment_id: &str) -> Result<i64, sqlx::Error> {
+        let span = info_span!("DeploymentManifest::count");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!" FROM deployment_manifest_files WHERE deployment_id = $1"#,
+            deployment_id
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// Returns the hashes referenced by the manifest that are not stored yet
+    pub async fn get_missing(db: &Database, deployment_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        let span = info_span!("DeploymentManifest::get_missing");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT m.file_hash as "file_hash!"
+            FROM deployment_manifest_files m
+            LEFT JOIN files f ON f.file_hash = m.file_hash AND f.file_deleted = FALSE
+            WHERE m.deployment_id = $1 AND f.file_id IS NULL
+            "#,
+            deployment_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// Whether the manifest of a deployment references the given hash
+    pub async fn references_hash(
+        db: &Database,
+        deployment_id: &str,
+        file_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let span = info_span!("DeploymentManifest::references_hash");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS (SELECT 1 FROM deployment_manifest_files WHERE deployment_id = $1 AND file_hash = $2)",
+            deployment_id,
+            file_hash
+        )
+        .fetch_one(&db.pool)
+        .await?;
+
+        Ok(exists.unwrap_or(false))
+    }
+
+    /// Moves the manifest into `deployment_files`, all blobs must have been uploaded beforehand
+    pub async fn finalize(db: &Database, deployment_id: &str) -> Result<(), sqlx::Error> {
+        let span = info_span!("DeploymentManifest::finalize");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let mut tx = db.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO deployment_files (deployment_id, file_id, file_path, mime_type)
+            SELECT m.deployment_id, f.file_id, m.file_path, m.mime_type
+            FROM deployment_manifest_files m
+            JOIN files f ON f.file_hash = m.file_hash
+            WHERE m.deployment_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+            deployment_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM deployment_manifest_files WHERE deployment_id = $1",
+            deployment_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use poem_openapi::{types::Example, Object};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Source control and CI information about a deployment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Object)]
+#[oai(example)]
+pub struct DeploymentMetadata {
+    /// Full or abbreviated git commit sha
+    pub commit_sha: Option<String>,
+    /// Branch name, a `refs/heads/` prefix is stripped
+    pub branch: Option<String>,
+    pub commit_message: Option<String>,
+    pub commit_author: Option<String>,
+    /// e.g. `github`, `gitlab` or `local`
+    pub ci_provider: Option<String>,
+    /// Link to the CI run that produced the deployment
+    pub ci_run_url: Option<String>,
+    /// Environment label, e.g. `production`, `staging` or `pr-123`
+    pub environment: Option<String>,
+}
+
+impl Example for DeploymentMetadata {
+    fn example() -> Self {
+        Self {
+            commit_sha: Some("f49a26f53118281e734941ef7e8cb374212d6436".to_string()),
+            branch: Some("main".to_string()),
+            commit_message: Some("Update build".to_string()),
+            commit_author: Some("Luc".to_string()),
+            ci_provider: Some("github".to_string()),
+            ci_run_url: Some("https://github.com/v3xlabs/edgeserver/actions/runs/13300406888".to_string()),
+            environment: Some("production".to_string()),
+        }
+    }
+}
+
+const MAX_COMMIT_MESSAGE_LENGTH: usize = 4096;
+const MAX_COMMIT_AUTHOR_LENGTH: usize = 255;
+
+impl DeploymentMetadata {
+    /// Validates and normalizes the metadata, returning a human readable reason when it is rejected
+    pub fn validate(self) -> Result<Self, String> {
+        let commit_sha = non_empty(self.commit_sha).map(|sha| sha.to_lowercase());
+        if let Some(sha) = &commit_sha {
+            if !(7..=64).contains(&sha.len()) || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Invalid commit sha: {:?}", sha));
+            }
+        }
+
+        let branch = non_empty(self.branch)
+            .map(|branch| branch.strip_prefix("refs/heads/").map(str::to_string).unwrap_or(branch));
+        if let Some(branch) = &branch {
+            if branch.len() > 255 || branch.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(format!("Invalid branch: {:?}", branch));
+            }
+        }
+
+        let commit_message = non_empty(self.commit_message);
+        if commit_message
+            .as_ref()
+            .is_some_and(|message| message.len() > MAX_COMMIT_MESSAGE_LENGTH)
+        {
+            return Err(format!(
+                "Commit message is longer than {} bytes",
+                MAX_COMMIT_MESSAGE_LENGTH
+            ));
+        }
+
+        let commit_author = non_empty(self.commit_author);
+        if commit_author.as_ref().is_some_and(|author| author.len() > MAX_COMMIT_AUTHOR_LENGTH) {
+            return Err("Commit author is too long".to_string());
+        }
+
+        let ci_provider = non_empty(self.ci_provider).map(|provider| provider.to_lowercase());
+        if let Some(provider) = &ci_provider {
+            if !is_label(provider) {
+                return Err(format!("Invalid ci provider: {:?}", provider));
+            }
+        }
+
+        let ci_run_url = non_empty(self.ci_run_url);
+        if let Some(url) = &ci_run_url {
+            if !(url.starts_with("https://") || url.starts_with("http://")) || url.len() > 2048 {
+                return Err(format!("Invalid ci run url: {:?}", url));
+            }
+        }
+
+        let environment = non_empty(self.environment).map(|environment| environment.to_lowercase());
+        if let Some(environment) = &environment {
+            if !is_label(environment) {
+                return Err(format!(
+                    "Invalid environment {:?}, use lowercase letters, digits and dashes",
+                    environment
+                ));
+            }
+        }
+
+        Ok(Self {
+            commit_sha,
+            branch,
+            commit_message,
+            commit_author,
+            ci_provider,
+            ci_run_url,
+            environment,
+        })
+    }
+
+    /// Lenient `validate` for metadata that was derived rather than sent
+    ///
+    /// Overlong commit messages and authors are truncated and every other invalid field is dropped
+    pub fn sanitize(self) -> Self {
+        let valid = |metadata: Self| metadata.validate().unwrap_or_default();
+
+        Self {
+            commit_sha: valid(Self {
+                commit_sha: self.commit_sha,
+                ..Default::default()
+            })
+            .commit_sha,
+            branch: valid(Self {
+                branch: self.branch,
+                ..Default::default()
+            })
+            .branch,
+            commit_message: valid(Self {
+                commit_message: self.commit_message.map(|message| truncate(message, MAX_COMMIT_MESSAGE_LENGTH)),
+                ..Default::default()
+            })
+            .commit_message,
+            commit_author: valid(Self {
+                commit_author: self.commit_author.map(|author| truncate(author, MAX_COMMIT_AUTHOR_LENGTH)),
+                ..Default::default()
+            })
+            .commit_author,
+            ci_provider: valid(Self {
+                ci_provider: self.ci_provider,
+                ..Default::default()
+            })
+            .ci_provider,
+            ci_run_url: valid(Self {
+                ci_run_url: self.ci_run_url,
+                ..Default::default()
+            })
+            .ci_run_url,
+            environment: valid(Self {
+                environment: self.environment,
+                ..Default::default()
+            })
+            .environment,
+        }
+    }
+
+    /// Extracts metadata from the legacy `context` string sent by the github action
+    pub fn from_context(context: &str) -> Option<Self> {
+        let context: Value = serde_json::from_str(context).ok()?;
+
+        if context.get("contextType")?.as_str()? != "github-action" {
+            return None;
+        }
+
+        let data = context.get("data")?;
+        let text = |value: Option<&Value>| value.and_then(Value::as_str).map(str::to_string);
+
+        let ci_run_url = match (
+            text(data.pointer("/commit/url")),
+            data.get("runId").and_then(Value::as_u64),
+        ) {
+            (Some(commit_url), Some(run_id)) => commit_url
+                .split_once("/commit/")
+                .map(|(repo_url, _)| format!("{}/actions/runs/{}", repo_url, run_id)),
+            _ => None,
+        };
+
+        Some(Self {
+            commit_sha: text(data.get("sha")),
+            branch: text(data.get("ref")),
+            commit_message: text(data.pointer("/commit/message")),
+            commit_author: text(data.pointer("/commit/author/name")),
+            ci_provider: Some("github".to_string()),
+            ci_run_url,
+            environment: None,
+        })
+    }
+}
+
+/// Cuts a string down to at most `max` bytes without splitting a character
+fn truncate(mut value: String, max: usize) -> String {
+    if value.len() > max {
+        let end = (0..=max).rev().find(|&end| value.is_char_boundary(end)).unwrap_or(0);
+        value.truncate(end);
+    }
+
+    value
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Lowercase letters, digits and dashes, at most 63 characters
+pub fn is_label(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 63
+        && !value.starts_with('-')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let metadata = DeploymentMetadata {
+            commit_sha: Some("F49A26F".to_string()),
+            branch: Some("refs/heads/main".to_string()),
+            environment: Some("PR-123".to_string()),
+            commit_message: Some("  ".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+
+        assert_eq!(metadata.commit_sha.as_deref(), Some("f49a26f"));
+        assert_eq!(metadata.branch.as_deref(), Some("main"));
+        assert_eq!(metadata.environment.as_deref(), Some("pr-123"));
+        assert_eq!(metadata.commit_message, None);
+
+        assert!(DeploymentMetadata {
+            commit_sha: Some("not-a-sha".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        assert!(DeploymentMetadata {
+            ci_run_url: Some("javascript:alert(1)".to_string()),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_from_context() {
+        let context = r#"{
+            "contextType": "github-action",
+            "data": {
+                "sha": "f49a26f53118281e734941ef7e8cb374212d6436",
+                "ref": "refs/heads/master",
+                "runId": 13300406888,
+                "commit": {
+                    "author": { "name": "Luc" },
+                    "message": "Update build",
+                    "url": "https://github.com/v3xlabs/env-md/commit/f49a26f53118281e734941ef7e8cb374212d6436"
+                }
+            }
+        }"#;
+
+        let metadata = DeploymentMetadata::from_context(context).unwrap().validate().unwrap();
+
+        assert_eq!(metadata.branch.as_deref(), Some("master"));
+        assert_eq!(metadata.commit_author.as_deref(), Some("Luc"));
+        assert_eq!(
+            metadata.ci_run_url.as_deref(),
+            Some("https://github.com/v3xlabs/env-md/actions/runs/13300406888")
+        );
+
+        assert_eq!(DeploymentMetadata::from_context("not json"), None);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let metadata = DeploymentMetadata {
+            commit_sha: Some("not-a-sha".to_string()),
+            branch: Some("refs/heads/main".to_string()),
+            commit_message: Some("é".repeat(3000)),
+            commit_author: Some("a".repeat(300)),
+            ..Default::default()
+        }
+        .sanitize();
+
+        assert_eq!(metadata.commit_sha, None);
+        assert_eq!(metadata.branch.as_deref(), Some("main"));
+        assert_eq!(metadata.commit_message, Some("é".repeat(2048)));
+        assert_eq!(metadata.commit_author, Some("a".repeat(255)));
+    }
+}
@@ -1,20 +1,40 @@
-use async_zip::base::read::mem::ZipFileReader;
+use std::io::SeekFrom;
+
+use async_compression::futures::bufread::{GzipDecoder, ZstdDecoder};
+use async_zip::base::read::seek::ZipFileReader;
 use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use futures::{io::BufReader, AsyncRead, AsyncSeekExt, StreamExt};
 use opentelemetry::Context;
 use poem_openapi::{types::Example, Object};
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_as};
-use tracing::{info, info_span};
+use sqlx::{query, query_as, query_scalar};
+use tracing::{info, info_span, warn};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
-    assets::AssetFile,
+    assets::{variants::FileVariant, AssetFile, SpooledFile},
     database::Database,
-    state::State,
+    gc,
+    state::{AppState, State},
     utils::id::{generate_id, IdType},
 };
 
+use archive::{normalize_entry_path, ArchiveFormat, InvalidEntryPath};
+use metadata::DeploymentMetadata;
+use events::{
+    DeploymentEvent, DeploymentFileEvent, DeploymentIpfsEvent, DeploymentPreviewEvent,
+    DeploymentStatus, DeploymentStatusEvent,
+};
+
+pub mod archive;
+pub mod events;
+pub mod manifest;
+pub mod metadata;
 pub mod preview;
+pub mod promotion;
+pub mod retention;
+pub mod rules;
 
 #[derive(Debug, Serialize, Deserialize, Object)]
 #[oai(example)]
@@ -25,6 +45,20 @@ pub struct Deployment {
     pub context: Option<String>,
     pub ipfs_cid: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// See `DeploymentStatus`
+    pub status: String,
+    pub status_message: Option<String>,
+    pub ipfs_status: Option<String>,
+    pub preview_status: Option<String>,
+    /// Pinned deployments are never removed by retention policies
+    pub pinned: bool,
+    pub commit_sha: Option<String>,
+    pub branch: Option<String>,
+    pub commit_message: Option<String>,
+    pub commit_author: Option<String>,
+    pub ci_provider: Option<String>,
+    pub ci_run_url: Option<String>,
+    pub environment: Option<String>,
 }
 
 impl Example for Deployment {
@@ -35,6 +69,18 @@ impl Example for Deployment {
             context: Some("{}".to_string()),
             ipfs_cid: Some("Qm1234567890...".to_string()),
             created_at: Utc::now(),
+            status: DeploymentStatus::Ready.as_str().to_string(),
+            status_message: None,
+            ipfs_status: Some("ready".to_string()),
+            preview_status: Some("ready".to_string()),
+            pinned: false,
+            commit_sha: Some("f49a26f53118281e734941ef7e8cb374212d6436".to_string()),
+            branch: Some("main".to_string()),
+            commit_message: Some("Update build".to_string()),
+            commit_author: Some("Luc".to_string()),
+            ci_provider: Some("github".to_string()),
+            ci_run_url: Some("https://github.com/v3xlabs/edgeserver/actions/runs/13300406888".to_string()),
+            environment: Some("production".to_string()),
         }
     }
 }
@@ -45,6 +91,7 @@ pub struct File {
     pub file_hash: String,
     pub file_size: Option<i64>,
     pub file_deleted: bool,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Object)]
@@ -60,6 +107,7 @@ impl Deployment {
         db: &Database,
         site_id: String,
         context: Option<String>,
+        metadata: DeploymentMetadata,
     ) -> Result<Self, sqlx::Error> {
         let span = info_span!("Deployment::new");
         span.set_parent(Context::current());
@@ -69,10 +117,24 @@ impl Deployment {
 
         query_as!(
             Deployment,
-            "INSERT INTO deployments (deployment_id, site_id, context) VALUES ($1, $2, $3) RETURNING *",
+            r#"
+            INSERT INTO deployments (
+                deployment_id, site_id, context,
+                commit_sha, branch, commit_message, commit_author, ci_provider, ci_run_url, environment
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *
+            "#,
             deployment_id,
             site_id,
-            context
+            context,
+            metadata.commit_sha,
+            metadata.branch,
+            metadata.commit_message,
+            metadata.commit_author,
+            metadata.ci_provider,
+            metadata.ci_run_url,
+            metadata.environment
         )
         .fetch_one(&db.pool)
         .await
@@ -88,125 +150,267 @@ impl Deployment {
     //     }
     // }
 
+    /// Ingests an archive that has been spooled to disk
+    ///
+    /// Entries are streamed out of the archive one at a time, so memory use does not grow with the archive size
     #[tracing::instrument(name = "upload_files", skip(self, state, file))]
-    pub async fn upload_files(&self, state: &State, file: Vec<u8>) -> Result<(), sqlx::Error> {
+    pub async fn upload_files(&self, state: &State, file: std::fs::File, format: ArchiveFormat) -> Result<(), color_eyre::eyre::Error> {
         let span = info_span!("Deployment::upload_files");
         span.set_parent(Context::current());
         let _guard = span.enter();
 
-        // TODO: Read file stream, extract zip file (contains multiple files), upload each file to s3 at the correct relevant path relative to deployment.deployment_id + '/'
+        let mut file = async_std::fs::File::from(file);
+        file.seek(SeekFrom::Start(0)).await?;
+
+        match format {
+            ArchiveFormat::Zip => self.upload_zip(state, file).await,
+            ArchiveFormat::Tar => self.upload_tar(state, file).await,
+            ArchiveFormat::TarGz => {
+                let mut decoder = GzipDecoder::new(BufReader::new(file));
+                decoder.multiple_members(true);
+                self.upload_tar(state, decoder).await
+            }
+            ArchiveFormat::TarZst => self.upload_tar(state, ZstdDecoder::new(BufReader::new(file))).await,
+        }
+    }
+
+    /// Ingests individually uploaded files, each named after its path relative to the site root
+    #[tracing::instrument(name = "upload_multipart_files", skip(self, state, files))]
+    pub async fn upload_multipart_files(&self, state: &State, files: Vec<(String, std::fs::File)>) -> Result<(), color_eyre::eyre::Error> {
+        let span = info_span!("Deployment::upload_multipart_files");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let total = files.len() as u64;
+        let mut processed = 0;
+
+        for (path, file) in files {
+            let mut file = async_std::fs::File::from(file);
+            file.seek(SeekFrom::Start(0)).await?;
+
+            let spooled = SpooledFile::spool(file).await?;
+
+            processed += 1;
+            self.catalog_file(state, &path, spooled, processed, Some(total)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Entry paths are checked against the central directory first, so a refused archive leaves no files behind
+    async fn upload_zip(&self, state: &State, file: async_std::fs::File) -> Result<(), color_eyre::eyre::Error> {
+        let mut zip = ZipFileReader::new(BufReader::new(file)).await?;
+
+        for entry in zip.file().entries() {
+            let raw_path = entry.filename().as_str().map_err(|_| {
+                InvalidEntryPath(String::from_utf8_lossy(entry.filename().as_bytes()).to_string())
+            })?;
+
+            if !entry.dir()? && normalize_entry_path(raw_path).is_none() {
+                return Err(InvalidEntryPath(raw_path.to_string()).into());
+            }
+        }
 
-        let zip = ZipFileReader::new(file).await.unwrap();
+        let total = zip
+            .file()
+            .entries()
+            .iter()
+            .filter(|entry| !entry.dir().unwrap_or(false))
+            .count() as u64;
+        let mut processed = 0;
 
         for index in 0..zip.file().entries().len() {
-            let file = zip.file().entries().get(index).unwrap();
-            let path = file.filename().as_str().unwrap();
-            let entry_is_dir = file.dir().unwrap();
+            let entry = zip.file().entries().get(index).unwrap();
+            let raw_path = entry.filename().as_str()?.to_string();
+            let crc32 = entry.crc32();
 
-            if entry_is_dir {
-                info!("Skipping directory: {:?}", path);
+            if entry.dir()? {
+                info!("Skipping directory: {:?}", raw_path);
                 continue;
             }
 
-            let mut file_content = zip.reader_with_entry(index).await.unwrap();
+            let path = normalize_entry_path(&raw_path).ok_or(InvalidEntryPath(raw_path))?;
 
-            let mut buf = Vec::new();
-            file_content.read_to_end_checked(&mut buf).await.unwrap();
+            let mut entry_reader = zip.reader_with_entry(index).await?;
 
-            // hash the file
-            let (_file, newly_created_file, file_hash, content_type, _file_size) =
-                AssetFile::from_buffer(state, &buf, path).await?;
+            // hash the file while spooling it to disk
+            let spooled = SpooledFile::spool(&mut entry_reader).await?;
 
-            info!("Cataloging metadata for file: {:?}", path);
+            if entry_reader.compute_hash() != crc32 {
+                return Err(eyre!("CRC32 mismatch for zip entry: {:?}", path));
+            }
 
-            let s = tracing::span!(
-                tracing::Level::INFO,
-                "cataloging_metadata",
-                file_path = path
-            );
-            let _enter = s.enter();
+            processed += 1;
+            self.catalog_file(state, &path, spooled, processed, Some(total)).await?;
+        }
 
-            let _deployment_file = query_as!(
-                 DeploymentFile,
-                "INSERT INTO deployment_files (deployment_id, file_id, file_path, mime_type) VALUES ($1, $2, $3, $4) RETURNING *",
-                self.deployment_id,
-                newly_created_file.file_id,
-                path,
-                content_type
-            ).fetch_one(&state.database.pool).await?;
+        Ok(())
+    }
 
-            drop(_enter);
+    async fn upload_tar(&self, state: &State, reader: impl AsyncRead + Unpin + Send) -> Result<(), color_eyre::eyre::Error> {
+        let mut entries = async_tar::Archive::new(reader).entries()?;
+        let mut processed = 0;
+
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            let raw_path = entry.path()?.to_string_lossy().to_string();
+
+            // directories, links & device files are not served
+            if !entry.header().entry_type().is_file() {
+                info!("Skipping non-file entry: {:?}", raw_path);
+                continue;
+            }
+
+            let path = normalize_entry_path(&raw_path).ok_or(InvalidEntryPath(raw_path))?;
 
-            if newly_created_file.is_new.unwrap_or_default() {
-                info!("Uploading file: {:?}", path);
-                let s = tracing::span!(tracing::Level::INFO, "uploading_file", file_path = path);
-                let _enter = s.enter();
+            let spooled = SpooledFile::spool(&mut entry).await?;
 
-                let s3_path = file_hash.to_string();
-                state
-                    .storage
-                    .bucket
-                    .put_object_with_content_type(&s3_path, &buf, &content_type)
-                    .await
-                    .unwrap();
+            processed += 1;
+            self.catalog_file(state, &path, spooled, processed, None).await?;
+        }
 
-                drop(_enter);
+        Ok(())
+    }
 
-                info!("Upload complete");
-            } else {
-                info!("File already exists, skipping upload");
+    /// Links a spooled file to this deployment, uploading its blob if it has not been seen before
+    async fn catalog_file(&self, state: &State, path: &str, mut spooled: SpooledFile, processed: u64, total: Option<u64>) -> Result<(), color_eyre::eyre::Error> {
+        let (_file, newly_created_file, file_hash, content_type, _file_size) =
+            AssetFile::from_spooled(state, &spooled, path).await?;
+
+        info!("Cataloging metadata for file: {:?}", path);
+
+        let s = tracing::span!(
+            tracing::Level::INFO,
+            "cataloging_metadata",
+            file_path = path
+        );
+        let _enter = s.enter();
+
+        let _deployment_file = query_as!(
+             DeploymentFile,
+            "INSERT INTO deployment_files (deployment_id, file_id, file_path, mime_type) VALUES ($1, $2, $3, $4) RETURNING *",
+            self.deployment_id,
+            newly_created_file.file_id,
+            path,
+            content_type
+        ).fetch_one(&state.database.pool).await?;
+
+        drop(_enter);
+
+        if newly_created_file.is_new.unwrap_or_default() {
+            info!("Uploading file: {:?}", path);
+            let s = tracing::span!(tracing::Level::INFO, "uploading_file", file_path = path);
+            let _enter = s.enter();
+
+            let s3_path = file_hash.to_string();
+            state
+                .storage
+                .bucket
+                .put_object_stream_with_content_type(&mut spooled.file, &s3_path, &content_type)
+                .await?;
+
+            drop(_enter);
+
+            info!("Upload complete");
+        } else {
+            info!("File already exists, skipping upload");
+        }
+
+        if let Some(file_id) = newly_created_file.file_id {
+            if let Err(error) = FileVariant::generate(state, file_id, &mut spooled, &content_type).await {
+                warn!("Failed to generate compressed variants of {:?}: {:?}", path, error);
             }
         }
 
+        state.events.publish(
+            &self.deployment_id,
+            DeploymentEvent::File(DeploymentFileEvent {
+                path: path.to_string(),
+                file_hash,
+                uploaded: newly_created_file.is_new.unwrap_or_default(),
+                processed,
+                total,
+            }),
+        );
+
         Ok(())
     }
 
-    // Go through all `files` where the `deployment_files` links it to a deployment_id from `deployments` table
-    // if the file is not used by deployments > cutoff_date then return the file
-    pub async fn cleanup_old_files(
-        state: &State,
-        cutoff_date: DateTime<Utc>,
-    ) -> Result<Vec<File>, sqlx::Error> {
-        let span = info_span!("Deployment::cleanup_old_files");
-        span.set_parent(Context::current());
-        let _guard = span.enter();
+    /// Removes a deployment together with its car and preview artifacts, its IPFS pin and the blobs no other deployment uses
+    ///
+    /// The rows go first so a failure can't leave a half removed deployment behind,
+    /// objects that can't be removed from the buckets afterwards are logged. Returns the blobs that were removed.
+    pub async fn delete(state: &AppState, deployment_id: &str) -> Result<Vec<gc::GcFile>, sqlx::Error> {
+        let started_at = Utc::now();
 
-        tracing::info!("Checking for unused files before: {:?}", cutoff_date);
-        let files = query_as!(
-            File,
-            r#"
-            SELECT DISTINCT f.* 
-            FROM files f
-            WHERE NOT EXISTS (
-                SELECT 1 
-                FROM deployment_files df
-                JOIN deployments d ON df.deployment_id = d.deployment_id
-                WHERE df.file_id = f.file_id 
-                AND d.created_at > $1
-            ) AND f.file_deleted = FALSE"#,
-            cutoff_date
+        // gathered up front, the rows pointing at them are removed along with the deployment
+        let preview_paths: Vec<String> = query!(
+            "SELECT preview_path, full_preview_path, favicon_path FROM deployment_previews WHERE deployment_id = $1",
+            deployment_id
         )
         .fetch_all(&state.database.pool)
+        .await?
+        .into_iter()
+        .flat_map(|preview| [Some(preview.preview_path), preview.full_preview_path, preview.favicon_path])
+        .flatten()
+        .collect();
+
+        let file_ids: Vec<i64> = query_scalar!(
+            "SELECT DISTINCT file_id FROM deployment_files WHERE deployment_id = $1",
+            deployment_id
+        )
+        .fetch_all(&state.database.pool)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let mut tx = state.database.pool.begin().await?;
+
+        // identical deployments produce the same cid, only unpin once the last one is gone
+        let cid = query_scalar!(
+            r#"
+            SELECT d.ipfs_cid
+            FROM deployments d
+            WHERE d.deployment_id = $1
+            AND NOT EXISTS (
+                SELECT 1 FROM deployments o WHERE o.ipfs_cid = d.ipfs_cid AND o.deployment_id != d.deployment_id
+            )
+            "#,
+            deployment_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .flatten();
+
+        query!(
+            "DELETE FROM deployments WHERE deployment_id = $1",
+            deployment_id
+        )
+        .execute(&mut *tx)
         .await?;
 
-        tracing::info!("Found {} unused files", files.len());
+        tx.commit().await?;
 
-        if files.len() > 0 {
-            tracing::info!("Deleting {} unused files", files.len());
+        state.cache.invalidate_deployment(deployment_id).await;
+        // environments may have been serving it
+        state.cache.invalidate_hosts();
 
-            // delete file from s3
-            for file in &files {
-                let s3_path = format!("sites/{}", file.file_hash);
-                state.storage.bucket.delete_object(&s3_path).await.unwrap();
+        if !gc::delete_artifacts(state, deployment_id, &preview_paths).await {
+            warn!("Some artifacts of deployment {} were left in the bucket", deployment_id);
+        }
+
+        if let (Some(ipfs), Some(cid)) = (&state.ipfs, cid) {
+            if let Err(error) = ipfs.unpin(&cid).await {
+                warn!("Failed to unpin {} for deployment {}: {:?}", cid, deployment_id, error);
             }
+        }
 
-            // delete the files from the `files` table
-            query!(
-                "UPDATE files SET file_deleted = TRUE WHERE file_id = ANY($1)",
-                &files.iter().map(|f| f.file_id).collect::<Vec<i64>>()
-            )
-            .execute(&state.database.pool)
-            .await?;
+        // files reused by an upload since are kept
+        let files = gc::delete_files(state, &file_ids, started_at).await?;
+
+        if !files.is_empty() {
+            state.cache.files.invalidate_all();
         }
 
         Ok(files)
@@ -226,6 +430,40 @@ impl Deployment {
         .await
     }
 
+    /// Pinned deployments are skipped by retention policies
+    pub async fn set_pinned(db: &Database, deployment_id: &str, pinned: bool) -> Result<Self, sqlx::Error> {
+        let span = info_span!("Deployment::set_pinned");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Deployment,
+            "UPDATE deployments SET pinned = $1 WHERE deployment_id = $2 RETURNING *",
+            pinned,
+            deployment_id
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// Returns the deployment that is currently live for a site
+    pub async fn get_active_by_site_id(
+        db: &Database,
+        site_id: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let span = info_span!("Deployment::get_active_by_site_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Deployment,
+            "SELECT d.* FROM deployments d JOIN sites s ON s.active_deployment_id = d.deployment_id WHERE s.site_id = $1",
+            site_id
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
     pub async fn update_context(
         db: &Database,
         deployment_id: &str,
@@ -245,6 +483,127 @@ impl Deployment {
         Ok(())
     }
 
+    /// Overwrites the metadata fields that are set, fields that are `None` are kept
+    pub async fn update_metadata(
+        db: &Database,
+        deployment_id: &str,
+        metadata: &DeploymentMetadata,
+    ) -> Result<(), sqlx::Error> {
+        let span = info_span!("Deployment::update_metadata");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!(
+            r#"
+            UPDATE deployments SET
+                commit_sha = COALESCE($1, commit_sha),
+                branch = COALESCE($2, branch),
+                commit_message = COALESCE($3, commit_message),
+                commit_author = COALESCE($4, commit_author),
+                ci_provider = COALESCE($5, ci_provider),
+                ci_run_url = COALESCE($6, ci_run_url),
+                environment = COALESCE($7, environment)
+            WHERE deployment_id = $8
+            "#,
+            metadata.commit_sha,
+            metadata.branch,
+            metadata.commit_message,
+            metadata.commit_author,
+            metadata.ci_provider,
+            metadata.ci_run_url,
+            metadata.environment,
+            deployment_id
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the deployment to a new lifecycle status and notifies event subscribers
+    pub async fn set_status(
+        state: &AppState,
+        deployment_id: &str,
+        status: DeploymentStatus,
+        message: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        let span = info_span!("Deployment::set_status");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!(
+            "UPDATE deployments SET status = $1, status_message = $2 WHERE deployment_id = $3",
+            status.as_str(),
+            message,
+            deployment_id
+        )
+        .execute(&state.database.pool)
+        .await?;
+
+        state.events.publish(
+            deployment_id,
+            DeploymentEvent::Status(DeploymentStatusEvent { status, message }),
+        );
+
+        Ok(())
+    }
+
+    pub async fn set_ipfs_status(
+        state: &AppState,
+        deployment_id: &str,
+        status: &str,
+        message: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE deployments SET ipfs_status = $1 WHERE deployment_id = $2",
+            status,
+            deployment_id
+        )
+        .execute(&state.database.pool)
+        .await?;
+
+        let cid = query_scalar!(
+            "SELECT ipfs_cid FROM deployments WHERE deployment_id = $1",
+            deployment_id
+        )
+        .fetch_one(&state.database.pool)
+        .await?;
+
+        state.events.publish(
+            deployment_id,
+            DeploymentEvent::Ipfs(DeploymentIpfsEvent {
+                status: status.to_string(),
+                cid,
+                message,
+            }),
+        );
+
+        Ok(())
+    }
+
+    pub async fn set_preview_status(
+        state: &AppState,
+        deployment_id: &str,
+        status: &str,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            "UPDATE deployments SET preview_status = $1 WHERE deployment_id = $2",
+            status,
+            deployment_id
+        )
+        .execute(&state.database.pool)
+        .await?;
+
+        state.events.publish(
+            deployment_id,
+            DeploymentEvent::Preview(DeploymentPreviewEvent {
+                status: status.to_string(),
+            }),
+        );
+
+        Ok(())
+    }
+
     pub async fn update_ipfs_cid(
         db: &Database,
         deployment_id: &str,
@@ -269,6 +628,18 @@ pub struct NewlyCreatedFile {
 }
 
 impl DeploymentFile {
+    /// Unlinks every file of a deployment, the blobs are left for garbage collection
+    pub async fn delete_by_deployment_id(db: &Database, deployment_id: &str) -> Result<u64, sqlx::Error> {
+        let span = info_span!("DeploymentFile::delete_by_deployment_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!("DELETE FROM deployment_files WHERE deployment_id = $1", deployment_id)
+            .execute(&db.pool)
+            .await
+            .map(|result| result.rows_affected())
+    }
+
     pub async fn get_deployment_files(
         db: &Database,
         deployment_id: &str,
@@ -287,7 +658,8 @@ impl DeploymentFile {
                 df.mime_type as "deployment_file_mime_type!",
                 f.file_hash as "file_hash!",
                 f.file_size,
-                f.file_deleted
+                f.file_deleted,
+                f.created_at as file_created_at
             FROM deployment_files df
             JOIN files f ON df.file_id = f.file_id
             WHERE df.deployment_id = $1
@@ -297,10 +669,81 @@ impl DeploymentFile {
         .fetch_all(&db.pool)
         .await
     }
+
+    pub async fn get_file_by_path(
+        db: &Database,
+        deployment_id: &str,
+        file_path: &str,
+    ) -> Result<Option<DeploymentFileEntry>, sqlx::Error> {
+        let span = info_span!("DeploymentFile::get_file_by_path");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            DeploymentFileEntry,
+            r#"
+            SELECT
+                df.deployment_id as "deployment_file_deployment_id!",
+                df.file_id as "deployment_file_file_id!",
+                df.file_path as "deployment_file_file_path!",
+                df.mime_type as "deployment_file_mime_type!",
+                f.file_hash as "file_hash!",
+                f.file_size,
+                f.file_deleted,
+                f.created_at as file_created_at
+            FROM deployment_files df
+            JOIN files f ON df.file_id = f.file_id
+            WHERE df.deployment_id = $1 AND df.file_path = $2
+            LIMIT 1
+            "#,
+            deployment_id,
+            file_path
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    /// Finds the first of several candidate paths that exists in the deployment
+    ///
+    /// Uses the `(deployment_id, file_path)` index, files whose blob has been deleted are only returned
+    /// when no other candidate exists.
+    pub async fn resolve_path(
+        db: &Database,
+        deployment_id: &str,
+        candidates: &[String],
+    ) -> Result<Option<DeploymentFileEntry>, sqlx::Error> {
+        let span = info_span!("DeploymentFile::resolve_path");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            DeploymentFileEntry,
+            r#"
+            SELECT
+                df.deployment_id as "deployment_file_deployment_id!",
+                df.file_id as "deployment_file_file_id!",
+                df.file_path as "deployment_file_file_path!",
+                df.mime_type as "deployment_file_mime_type!",
+                f.file_hash as "file_hash!",
+                f.file_size,
+                f.file_deleted,
+                f.created_at as file_created_at
+            FROM deployment_files df
+            JOIN files f ON df.file_id = f.file_id
+            WHERE df.deployment_id = $1 AND df.file_path = ANY($2)
+            ORDER BY f.file_deleted, array_position($2, df.file_path)
+            LIMIT 1
+            "#,
+            deployment_id,
+            candidates
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
 }
 
 // Add this new struct to represent the joined result
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, Object)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, Object)]
 pub struct DeploymentFileEntry {
     pub deployment_file_deployment_id: String,
     pub deployment_file_file_id: i64,
@@ -309,4 +752,6 @@ pub struct DeploymentFileEntry {
     pub file_hash: String,
     pub file_size: Option<i64>,
     pub file_deleted: bool,
+    /// When the blob was stored, used as `Last-Modified` when serving
+    pub file_created_at: DateTime<Utc>,
 }
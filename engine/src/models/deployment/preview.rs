@@ -78,3 +78,41 @@ impl DeploymentPreview {
         Ok(rows)
     }
 }
+
+/// Hostname a deployment is reachable at under the preview base domain
+///
+/// `d_1234567890` becomes `d-1234567890.preview.example.com`, underscores are not valid in hostnames
+pub fn preview_hostname(deployment_id: &str, base_domain: &str) -> String {
+    format!("{}.{}", deployment_id.replace('_', "-"), base_domain)
+}
+
+/// Reverse of `preview_hostname`, returns the deployment id a preview host points at
+pub fn deployment_id_from_preview_host(host: &str, base_domain: &str) -> Option<String> {
+    let label = host.strip_suffix(base_domain)?.strip_suffix('.')?;
+    let id = label.strip_prefix("d-")?;
+
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(format!("d_{}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_hostname() {
+        let host = preview_hostname("d_1234567890", "preview.example.com");
+        assert_eq!(host, "d-1234567890.preview.example.com");
+
+        assert_eq!(
+            deployment_id_from_preview_host(&host, "preview.example.com"),
+            Some("d_1234567890".to_string())
+        );
+        assert_eq!(deployment_id_from_preview_host("preview.example.com", "preview.example.com"), None);
+        assert_eq!(deployment_id_from_preview_host("a.d-123.preview.example.com", "preview.example.com"), None);
+        assert_eq!(deployment_id_from_preview_host("d-123.notpreview.example.com", "preview.example.com"), None);
+    }
+}
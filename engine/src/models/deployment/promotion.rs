@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use opentelemetry::Context;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::{info, info_span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::events::DeploymentStatus;
+use crate::{database::Database, models::site::Site, routes::error::HttpError};
+
+/// A record of a deployment being made live for a site
+#[derive(Debug, Serialize, Deserialize, Object, FromRow)]
+pub struct DeploymentPromotion {
+    pub promotion_id: i64,
+    pub site_id: String,
+    pub deployment_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Why a deployment couldn't be made live
+#[derive(Debug, thiserror::Error)]
+pub enum PromotionError {
+    #[error("Deployment not found")]
+    NotFound,
+    #[error("The deployment is {0}, only ready deployments can be made live")]
+    NotReady(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<PromotionError> for HttpError {
+    fn from(error: PromotionError) -> Self {
+        match error {
+            PromotionError::NotFound => HttpError::NotFound,
+            PromotionError::NotReady(_) => HttpError::Conflict(error.to_string()),
+            PromotionError::Database(error) => HttpError::DatabaseError(error),
+        }
+    }
+}
+
+impl DeploymentPromotion {
+    /// Makes `deployment_id` the live deployment of `site_id`
+    ///
+    /// Only `ready` deployments of the site can be promoted
+    pub async fn promote(db: &Database, site_id: &str, deployment_id: &str) -> Result<Site, PromotionError> {
+        let span = info_span!("DeploymentPromotion::promote");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let mut tx = db.pool.begin().await?;
+
+        // lock the site so concurrent promotes & rollbacks are serialized
+        sqlx::query!(
+            "SELECT site_id FROM sites WHERE site_id = $1 FOR UPDATE",
+            site_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let status = sqlx::query_scalar!(
+            "SELECT status FROM deployments WHERE deployment_id = $1 AND site_id = $2",
+            deployment_id,
+            site_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(PromotionError::NotFound)?;
+
+        if status != DeploymentStatus::Ready.as_str() {
+            return Err(PromotionError::NotReady(status));
+        }
+
+        sqlx::query!(
+            "INSERT INTO deployment_promotions (site_id, deployment_id) VALUES ($1, $2)",
+            site_id,
+            deployment_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let site = sqlx::query_as!(
+            Site,
+            "UPDATE sites SET active_deployment_id = $1 WHERE site_id = $2 RETURNING *",
+            deployment_id,
+            site_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        info!("Promoted deployment {} for site {}", deployment_id, site_id);
+
+        Ok(site)
+    }
+
+    /// Flips the site back to the previously promoted deployment that is still `ready`
+    ///
+    /// The promotions after it are dropped from the history.
+    /// Returns `None` if there is no earlier promotion to roll back to
+    pub async fn rollback(db: &Database, site_id: &str) -> Result<Option<Site>, sqlx::Error> {
+        let span = info_span!("DeploymentPromotion::rollback");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let mut tx = db.pool.begin().await?;
+
+        sqlx::query!(
+            "SELECT site_id FROM sites WHERE site_id = $1 FOR UPDATE",
+            site_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let current = sqlx::query_as!(
+            DeploymentPromotion,
+            "SELECT * FROM deployment_promotions WHERE site_id = $1 ORDER BY promotion_id DESC LIMIT 1",
+            site_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(current) = current else {
+            return Ok(None);
+        };
+
+        let previous = sqlx::query_as!(
+            DeploymentPromotion,
+            r#"
+            SELECT p.* FROM deployment_promotions p
+            JOIN deployments d ON d.deployment_id = p.deployment_id
+            WHERE p.site_id = $1 AND p.promotion_id < $2 AND p.deployment_id <> $3 AND d.status = 'ready'
+            ORDER BY p.promotion_id DESC
+            LIMIT 1
+            "#,
+            site_id,
+            current.promotion_id,
+            current.deployment_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+
+        sqlx::query!(
+            "DELETE FROM deployment_promotions WHERE site_id = $1 AND promotion_id > $2",
+            site_id,
+            previous.promotion_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let site = sqlx::query_as!(
+            Site,
+            "UPDATE sites SET active_deployment_id = $1 WHERE site_id = $2 RETURNING *",
+            previous.deployment_id,
+            site_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        info!(
+            "Rolled back site {} from {} to {}",
+            site_id, current.deployment_id, previous.deployment_id
+        );
+
+        Ok(Some(site))
+    }
+
+    pub async fn get_by_site_id(db: &Database, site_id: &str) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("DeploymentPromotion::get_by_site_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        sqlx::query_as!(
+            DeploymentPromotion,
+            "SELECT * FROM deployment_promotions WHERE site_id = $1 ORDER BY promotion_id DESC",
+            site_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+}
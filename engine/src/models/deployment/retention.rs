@@ -0,0 +1,54 @@
+use sqlx::query_scalar;
+use tracing::info;
+
+use crate::state::AppState;
+
+use super::Deployment;
+
+pub struct DeploymentRetention;
+
+impl DeploymentRetention {
+    /// Removes the deployments that fall outside the retention policy of their site
+    ///
+    /// A deployment is kept while it is among the newest `retention_keep_last` or younger than `retention_days`.
+    /// The live deployment, deployments an environment serves, pinned deployments and deployments that are still uploading are always kept.
+    #[tracing::instrument(name = "DeploymentRetention::enforce", skip(state))]
+    pub async fn enforce(state: &AppState) -> Result<Vec<String>, sqlx::Error> {
+        let expired = query_scalar!(
+            r#"
+            SELECT d.deployment_id
+            FROM (
+                SELECT
+                    deployment_id,
+                    site_id,
+                    created_at,
+                    status,
+                    pinned,
+                    ROW_NUMBER() OVER (PARTITION BY site_id ORDER BY created_at DESC) AS position
+                FROM deployments
+            ) d
+            JOIN sites s ON s.site_id = d.site_id
+            WHERE (s.retention_keep_last IS NOT NULL OR s.retention_days IS NOT NULL)
+            AND (s.retention_keep_last IS NULL OR d.position > s.retention_keep_last)
+            AND (s.retention_days IS NULL OR d.created_at < NOW() - make_interval(days => s.retention_days))
+            AND d.deployment_id IS DISTINCT FROM s.active_deployment_id
+            AND d.pinned = FALSE
+            AND d.status NOT IN ('created', 'uploading', 'processing')
+            AND NOT EXISTS (SELECT 1 FROM site_environments e WHERE e.deployment_id = d.deployment_id)
+            "#
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        let mut deleted = Vec::with_capacity(expired.len());
+
+        for deployment_id in expired {
+            Deployment::delete(state, &deployment_id).await?;
+            deleted.push(deployment_id);
+        }
+
+        info!("Removed {} expired deployments", deleted.len());
+
+        Ok(deleted)
+    }
+}
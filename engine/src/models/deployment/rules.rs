@@ -0,0 +1,913 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use opentelemetry::Context;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use poem::http::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, types::Json};
+use tracing::{info, info_span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{database::Database, routes::error::HttpError, state::AppState};
+
+use super::{DeploymentFile, DeploymentFileEntry};
+
+/// Netlify style rules file at the root of a deployment
+pub const REDIRECTS_FILE: &str = "_redirects";
+/// Netlify style response headers file at the root of a deployment
+pub const HEADERS_FILE: &str = "_headers";
+/// Configuration file at the root of a deployment, its `redirects` and `headers` arrays are compiled into rules
+pub const CONFIG_FILE: &str = "edgeserver.json";
+
+/// Rules files larger than this are rejected
+const MAX_RULES_FILE_SIZE: i64 = 1024 * 1024;
+const MAX_RULES: usize = 10_000;
+
+/// Statuses a rule can have, 3xx redirect the client and the others serve the target with that status
+const RULE_STATUSES: [u16; 9] = [200, 301, 302, 303, 307, 308, 404, 410, 451];
+
+/// Headers that describe the transfer of the body rather than the content, these are set by the server
+const FORBIDDEN_HEADERS: [&str; 9] = [
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-range",
+    "keep-alive",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Headers the server computes for the file or redirect it answers with, rules can't replace them
+const SERVER_HEADERS: [&str; 5] = ["accept-ranges", "content-type", "etag", "last-modified", "location"];
+
+/// A single redirect or rewrite rule
+///
+/// `from` is matched against the request path, `:name` segments match a single path segment and a
+/// trailing `*` matches the rest of the path. Both are substituted into `to`, the latter as `:splat`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+    /// Apply the rule even when a file exists at the requested path
+    pub force: bool,
+    /// Query parameters the request must have, a `:name` value matches any value
+    pub query: Vec<(String, String)>,
+}
+
+/// Headers added to responses for paths matching `path`, a `*` matches any part of the path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeaderRule {
+    pub path: String,
+    /// Lowercase header names and their values
+    pub headers: Vec<(String, String)>,
+}
+
+/// What to do with a request that matched a rule
+#[derive(Debug, PartialEq)]
+pub enum RuleMatch {
+    /// Send the client to `location`
+    Redirect { location: String, status: u16 },
+    /// Serve the file at `path` of the deployment instead
+    Rewrite { path: String, status: u16 },
+}
+
+/// The compiled rules of a deployment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentRules {
+    pub deployment_id: String,
+    pub redirects: Json<Vec<RedirectRule>>,
+    pub created_at: DateTime<Utc>,
+    pub headers: Json<Vec<HeaderRule>>,
+}
+
+/// The parts of `edgeserver.json` that are read at upload
+#[derive(Debug, Default, Deserialize)]
+pub struct EdgeserverConfig {
+    #[serde(default)]
+    pub redirects: Vec<ConfigRedirect>,
+    #[serde(default)]
+    pub headers: Vec<ConfigHeaders>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigRedirect {
+    pub from: String,
+    pub to: String,
+    /// Defaults to 301
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigHeaders {
+    #[serde(rename = "for")]
+    pub path: String,
+    pub values: BTreeMap<String, String>,
+}
+
+impl DeploymentRules {
+    pub async fn get_by_deployment_id(
+        db: &Database,
+        deployment_id: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let span = info_span!("DeploymentRules::get_by_deployment_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            DeploymentRules,
+            r#"
+            SELECT
+                deployment_id,
+                redirects as "redirects: Json<Vec<RedirectRule>>",
+                created_at,
+                headers as "headers: Json<Vec<HeaderRule>>"
+            FROM deployment_rules
+            WHERE deployment_id = $1
+            "#,
+            deployment_id
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    pub async fn set(
+        db: &Database,
+        deployment_id: &str,
+        redirects: Vec<RedirectRule>,
+        headers: Vec<HeaderRule>,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("DeploymentRules::set");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            DeploymentRules,
+            r#"
+            INSERT INTO deployment_rules (deployment_id, redirects, headers)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (deployment_id) DO UPDATE
+            SET redirects = EXCLUDED.redirects, headers = EXCLUDED.headers, created_at = NOW()
+            RETURNING
+                deployment_id,
+                redirects as "redirects: Json<Vec<RedirectRule>>",
+                created_at,
+                headers as "headers: Json<Vec<HeaderRule>>"
+            "#,
+            deployment_id,
+            Json(redirects) as _,
+            Json(headers) as _
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    pub async fn delete(db: &Database, deployment_id: &str) -> Result<(), sqlx::Error> {
+        let span = info_span!("DeploymentRules::delete");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query!(
+            "DELETE FROM deployment_rules WHERE deployment_id = $1",
+            deployment_id
+        )
+        .execute(&db.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Compiles the rules files uploaded to a deployment and stores the result
+    ///
+    /// Rules from `_redirects` and `_headers` come before the ones from `edgeserver.json`. An invalid file is
+    /// rejected with a `BadRequest` naming the file and lines, a deployment without rules files has its stored
+    /// rules removed.
+    pub async fn compile(state: &AppState, deployment_id: &str) -> Result<Option<Self>, HttpError> {
+        let mut redirects = Vec::new();
+        let mut headers = Vec::new();
+        let mut found = false;
+
+        for file_path in [REDIRECTS_FILE, HEADERS_FILE, CONFIG_FILE] {
+            let Some(file) = DeploymentFile::get_file_by_path(&state.database, deployment_id, file_path).await? else {
+                continue;
+            };
+
+            found = true;
+
+            let contents = read_rules_file(state, &file).await?;
+            let invalid = |error| HttpError::BadRequest(format!("Invalid {}: {}", file_path, error));
+
+            match file_path {
+                REDIRECTS_FILE => redirects.extend(parse_redirects(&contents).map_err(invalid)?),
+                HEADERS_FILE => headers.extend(parse_headers(&contents).map_err(invalid)?),
+                _ => {
+                    let (config_redirects, config_headers) = parse_config(&contents).map_err(invalid)?;
+
+                    redirects.extend(config_redirects);
+                    headers.extend(config_headers);
+                }
+            }
+        }
+
+        if !found {
+            DeploymentRules::delete(&state.database, deployment_id).await?;
+
+            return Ok(None);
+        }
+
+        if redirects.len() > MAX_RULES || headers.len() > MAX_RULES {
+            return Err(HttpError::BadRequest(format!(
+                "A deployment can have at most {} redirect and {} header rules",
+                MAX_RULES, MAX_RULES
+            )));
+        }
+
+        info!(
+            "Compiled {} redirect and {} header rules for deployment: {:?}",
+            redirects.len(),
+            headers.len(),
+            deployment_id
+        );
+
+        Ok(Some(DeploymentRules::set(&state.database, deployment_id, redirects, headers).await?))
+    }
+}
+
+async fn read_rules_file(state: &AppState, file: &DeploymentFileEntry) -> Result<String, HttpError> {
+    let file_path = &file.deployment_file_file_path;
+
+    if file.file_size.unwrap_or_default() > MAX_RULES_FILE_SIZE {
+        return Err(HttpError::BadRequest(format!(
+            "{} is larger than {} bytes",
+            file_path, MAX_RULES_FILE_SIZE
+        )));
+    }
+
+    let response = state
+        .storage
+        .bucket
+        .get_object(&file.file_hash)
+        .await
+        .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+    if response.status_code() != 200 {
+        return Err(HttpError::AnyhowError(eyre!(
+            "Reading {} failed with status {}",
+            file_path,
+            response.status_code()
+        )));
+    }
+
+    String::from_utf8(response.bytes().to_vec())
+        .map_err(|_| HttpError::BadRequest(format!("{} is not valid utf-8", file_path)))
+}
+
+/// Whether a deployment file is a rules file, these are never served
+pub fn is_rules_file(file_path: &str) -> bool {
+    file_path == REDIRECTS_FILE || file_path == HEADERS_FILE || file_path == CONFIG_FILE
+}
+
+impl RedirectRule {
+    /// Validates a rule, returning a human readable reason when it is rejected
+    pub fn new(
+        from: &str,
+        to: &str,
+        status: u16,
+        force: bool,
+        query: Vec<(String, String)>,
+    ) -> Result<Self, String> {
+        if from.starts_with("http://") || from.starts_with("https://") {
+            return Err(format!("rules for other hosts are not supported: {:?}", from));
+        }
+
+        if !from.starts_with('/') {
+            return Err(format!("the source path must start with /: {:?}", from));
+        }
+
+        let segments: Vec<&str> = from.split('/').collect();
+        for (index, segment) in segments.iter().enumerate() {
+            if segment.contains('*') && (*segment != "*" || index != segments.len() - 1) {
+                return Err(format!("* is only allowed as the last path segment: {:?}", from));
+            }
+
+            if let Some(name) = segment.strip_prefix(':') {
+                if !is_placeholder_name(name) {
+                    return Err(format!("invalid placeholder {:?}", segment));
+                }
+            }
+        }
+
+        let external = to.starts_with("http://") || to.starts_with("https://");
+        if !external && !to.starts_with('/') {
+            return Err(format!("the target must be a path or an http(s) url: {:?}", to));
+        }
+
+        if !RULE_STATUSES.contains(&status) {
+            return Err(format!("unsupported status {}", status));
+        }
+
+        if external && !(300..400).contains(&status) {
+            return Err(format!("only redirects can point at another host: {:?}", to));
+        }
+
+        if query.iter().any(|(key, _)| key.is_empty()) {
+            return Err("query conditions need a parameter name".to_string());
+        }
+
+        Ok(Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            force,
+            query,
+        })
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.status)
+    }
+
+    /// Matches the rule against a decoded request path and query parameters, returning the target with the captured path segments percent-encoded again
+    fn matches(&self, path: &str, query: &[(&str, &str)]) -> Option<String> {
+        let mut captures: Vec<(&str, String)> = Vec::new();
+        let mut segments = trim_trailing_slash(path).split('/');
+
+        for part in trim_trailing_slash(&self.from).split('/') {
+            if part == "*" {
+                let splat = segments.by_ref().collect::<Vec<_>>().join("/");
+                return self.match_query(captures, Some(encode_path(&splat)), query);
+            }
+
+            let segment = segments.next()?;
+
+            match part.strip_prefix(':') {
+                Some(name) if !segment.is_empty() => captures.push((name, encode_path(segment))),
+                Some(_) => return None,
+                None if part == segment => {}
+                None => return None,
+            }
+        }
+
+        if segments.next().is_some() {
+            return None;
+        }
+
+        self.match_query(captures, None, query)
+    }
+
+    fn match_query<'a>(
+        &'a self,
+        mut captures: Vec<(&'a str, String)>,
+        splat: Option<String>,
+        query: &[(&str, &str)],
+    ) -> Option<String> {
+        for (key, value) in &self.query {
+            let (_, actual) = query.iter().find(|(name, _)| *name == key.as_str())?;
+
+            match value.strip_prefix(':') {
+                Some(name) => captures.push((name, actual.to_string())),
+                None if value.as_str() == *actual => {}
+                None => return None,
+            }
+        }
+
+        if let Some(splat) = splat {
+            captures.push(("splat", splat));
+        }
+
+        Some(expand(&self.to, &captures))
+    }
+}
+
+/// Finds the rule that applies to a request
+///
+/// Rules are tried in order, the first match wins. Rules without `force` are skipped when a file exists at the
+/// requested path. Redirects pass the query string along unless the rule matched on it or sets its own.
+pub fn resolve(
+    rules: &[RedirectRule],
+    path: &str,
+    query: Option<&str>,
+    file_exists: bool,
+) -> Option<RuleMatch> {
+    // matched like the files are looked up, `/caf%C3%A9` matches a rule for `/café`
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    let query = query.unwrap_or_default();
+    let pairs: Vec<(&str, &str)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect();
+
+    rules
+        .iter()
+        .filter(|rule| rule.force || !file_exists)
+        .find_map(|rule| {
+            let target = rule.matches(&path, &pairs)?;
+
+            if rule.is_redirect() {
+                let location = if rule.query.is_empty() && !query.is_empty() && !target.contains('?') {
+                    format!("{}?{}", target, query)
+                } else {
+                    target
+                };
+
+                Some(RuleMatch::Redirect {
+                    location,
+                    status: rule.status,
+                })
+            } else {
+                let path = target.split(['?', '#']).next().unwrap_or_default().to_string();
+
+                Some(RuleMatch::Rewrite {
+                    path,
+                    status: rule.status,
+                })
+            }
+        })
+}
+
+/// Collects the headers of every rule matching the request path
+///
+/// When several rules set the same header their values are joined with `, `.
+/// Headers the server computes are left out, rules stored before they were refused may still contain them
+pub fn resolve_headers(rules: &[HeaderRule], path: &str) -> Vec<(String, String)> {
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    let path = trim_trailing_slash(&path);
+    let mut headers: Vec<(String, String)> = Vec::new();
+
+    for rule in rules.iter().filter(|rule| glob_matches(trim_trailing_slash(&rule.path), path)) {
+        for (name, value) in rule.headers.iter().filter(|(name, _)| !is_reserved_header(name)) {
+            match headers.iter_mut().find(|(existing, _)| existing == name) {
+                Some((_, existing)) => {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                }
+                None => headers.push((name.clone(), value.clone())),
+            }
+        }
+    }
+
+    headers
+}
+
+/// Parses a `_headers` file
+///
+/// Unindented lines are paths, the indented `Name: value` lines below them are the headers for that path.
+/// Every invalid line is reported, not only the first.
+pub fn parse_headers(contents: &str) -> Result<Vec<HeaderRule>, String> {
+    let mut rules: Vec<HeaderRule> = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, line) in contents.trim_start_matches('\u{feff}').lines().enumerate() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let result = if !line.starts_with(char::is_whitespace) {
+            validate_header_path(trimmed).map(|path| {
+                rules.push(HeaderRule {
+                    path,
+                    headers: Vec::new(),
+                })
+            })
+        } else {
+            match rules.last_mut() {
+                Some(rule) => trimmed
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected `Name: value`, got {:?}", trimmed))
+                    .and_then(|(name, value)| validate_header(name.trim(), value.trim()))
+                    .map(|header| rule.headers.push(header)),
+                None => Err("headers must follow a path".to_string()),
+            }
+        };
+
+        if let Err(error) = result {
+            errors.push(format!("line {}: {}", index + 1, error));
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("; "));
+    }
+
+    Ok(rules)
+}
+
+fn validate_header_path(path: &str) -> Result<String, String> {
+    if !path.starts_with('/') || path.contains(char::is_whitespace) {
+        return Err(format!("a path must start with / and can't contain spaces: {:?}", path));
+    }
+
+    Ok(path.to_string())
+}
+
+/// Validates a header name and value, returning the lowercased name
+fn validate_header(name: &str, value: &str) -> Result<(String, String), String> {
+    let header_name =
+        HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name {:?}", name))?;
+
+    if FORBIDDEN_HEADERS.contains(&header_name.as_str()) {
+        return Err(format!("the {} header can't be set", header_name));
+    }
+
+    if SERVER_HEADERS.contains(&header_name.as_str()) {
+        return Err(format!("the {} header is set by the server", header_name));
+    }
+
+    HeaderValue::from_str(value).map_err(|_| format!("invalid value for header {}", header_name))?;
+
+    Ok((header_name.as_str().to_string(), value.to_string()))
+}
+
+fn is_reserved_header(name: &str) -> bool {
+    FORBIDDEN_HEADERS.contains(&name) || SERVER_HEADERS.contains(&name)
+}
+
+/// Matches a path against a pattern where `*` matches any sequence of characters, including `/`
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, path) = (pattern.as_bytes(), path.as_bytes());
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < path.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == path[s] {
+            p += 1;
+            s += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            s = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Parses a `_redirects` file
+///
+/// Every line is `from [key=value ...] to [status][!]`, blank lines and lines starting with `#` are ignored
+pub fn parse_redirects(contents: &str) -> Result<Vec<RedirectRule>, String> {
+    let mut rules = Vec::new();
+
+    for (index, line) in contents.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let rule = parse_redirect_line(line).map_err(|error| format!("line {}: {}", index + 1, error))?;
+
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+fn parse_redirect_line(line: &str) -> Result<RedirectRule, String> {
+    let mut tokens = line.split_whitespace().peekable();
+
+    let from = tokens.next().ok_or("missing source path")?;
+
+    let mut query = Vec::new();
+    while let Some(token) = tokens.next_if(|token| !is_target(token)) {
+        let (key, value) = token
+            .split_once('=')
+            .ok_or_else(|| format!("expected a key=value query condition or a target, got {:?}", token))?;
+
+        query.push((key.to_string(), value.to_string()));
+    }
+
+    let to = tokens.next().ok_or("missing target")?;
+
+    let (status, force) = match tokens.next() {
+        Some(token) => {
+            let (code, force) = match token.strip_suffix('!') {
+                Some(code) => (code, true),
+                None => (token, false),
+            };
+
+            let status = code
+                .parse::<u16>()
+                .map_err(|_| format!("invalid status {:?}", token))?;
+
+            (status, force)
+        }
+        None => (301, false),
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(format!("unsupported condition {:?}", token));
+    }
+
+    RedirectRule::new(from, to, status, force, query)
+}
+
+/// Parses the `redirects` and `headers` arrays of an `edgeserver.json` file
+pub fn parse_config(contents: &str) -> Result<(Vec<RedirectRule>, Vec<HeaderRule>), String> {
+    let config: EdgeserverConfig = serde_json::from_str(contents).map_err(|error| error.to_string())?;
+
+    let redirects = config
+        .redirects
+        .into_iter()
+        .enumerate()
+        .map(|(index, redirect)| {
+            RedirectRule::new(
+                &redirect.from,
+                &redirect.to,
+                redirect.status.unwrap_or(301),
+                redirect.force,
+                redirect.query.into_iter().collect(),
+            )
+            .map_err(|error| format!("redirect {}: {}", index, error))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let headers = config
+        .headers
+        .into_iter()
+        .enumerate()
+        .map(|(index, rule)| {
+            compile_config_headers(rule).map_err(|error| format!("headers {}: {}", index, error))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((redirects, headers))
+}
+
+fn compile_config_headers(rule: ConfigHeaders) -> Result<HeaderRule, String> {
+    let path = validate_header_path(&rule.path)?;
+    let headers = rule
+        .values
+        .iter()
+        .map(|(name, value)| validate_header(name, value))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(HeaderRule { path, headers })
+}
+
+fn is_target(token: &str) -> bool {
+    token.starts_with('/') || token.starts_with("http://") || token.starts_with("https://")
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn trim_trailing_slash(path: &str) -> &str {
+    if path.len() > 1 {
+        path.trim_end_matches('/')
+    } else {
+        path
+    }
+}
+
+/// Substitutes `:name` placeholders in a target, unknown placeholders (and ports) are left as is
+fn expand(target: &str, captures: &[(&str, String)]) -> String {
+    let mut result = String::with_capacity(target.len());
+    let mut rest = target;
+
+    while let Some(index) = rest.find(':') {
+        result.push_str(&rest[..index]);
+
+        let after = &rest[index + 1..];
+        let length = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        let name = &after[..length];
+
+        match captures.iter().rev().find(|(capture, _)| *capture == name) {
+            Some((_, value)) if !name.is_empty() => result.push_str(value),
+            _ => {
+                result.push(':');
+                result.push_str(name);
+            }
+        }
+
+        rest = &after[length..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Characters of a decoded path that can't appear as they are in a location or rewritten path, `/` is kept
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, PATH_ENCODE_SET).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_redirects() {
+        let rules = parse_redirects(
+            "# comment\n\
+             /old /new\n\
+             \n\
+             /store id=:id /products/:id 302\n\
+             /app/* /app/index.html 200!\n",
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].status, 301);
+        assert_eq!(rules[1].query, vec![("id".to_string(), ":id".to_string())]);
+        assert_eq!(rules[1].status, 302);
+        assert!(rules[2].force);
+
+        assert!(parse_redirects("/a").unwrap_err().starts_with("line 1"));
+        assert!(parse_redirects("/a /b 999").is_err());
+        assert!(parse_redirects("/a /b 301 Country=us").is_err());
+        assert!(parse_redirects("/a/*/b /c").is_err());
+        assert!(parse_redirects("/a https://example.com 200").is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let rules = parse_config(
+            r#"{ "redirects": [{ "from": "/blog/:slug", "to": "/posts/:slug", "status": 308, "query": { "v": "1" } }] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(rules[0].status, 308);
+        assert_eq!(rules[0].query, vec![("v".to_string(), "1".to_string())]);
+
+        let (_, headers) = parse_config(
+            r#"{ "headers": [{ "for": "/*", "values": { "Content-Security-Policy": "default-src 'self'" } }] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            headers[0].headers,
+            vec![("content-security-policy".to_string(), "default-src 'self'".to_string())]
+        );
+
+        assert!(parse_config(r#"{ "redirects": [{ "from": "/a", "too": "/b" }] }"#).is_err());
+        assert!(parse_config(r#"{ "headers": [{ "for": "/*", "values": { "Content-Length": "1" } }] }"#).is_err());
+
+        let (redirects, headers) = parse_config("{}").unwrap();
+        assert!(redirects.is_empty() && headers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let rules = parse_headers(
+            "/*\n\
+             \x20 X-Frame-Options: DENY\n\
+             \x20 Content-Security-Policy: default-src 'self'\n\
+             \n\
+             /assets/*\n\
+             \x20 Cache-Control: public, max-age=31536000, immutable\n",
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].headers[0], ("x-frame-options".to_string(), "DENY".to_string()));
+        assert_eq!(rules[1].path, "/assets/*");
+
+        let error = parse_headers(
+            "  X-Early: 1\n/a\n  Bad Header: 1\n  Transfer-Encoding: chunked\n  ETag: \"1\"\n",
+        )
+        .unwrap_err();
+        for line in ["line 1", "line 3", "line 4", "line 5"] {
+            assert!(error.contains(line), "{} not reported in {:?}", line, error);
+        }
+    }
+
+    #[test]
+    fn test_resolve_headers() {
+        let rules = parse_headers(
+            "/*\n  Vary: Accept\n/assets/*.js\n  Vary: Origin\n  Cache-Control: immutable\n/about/\n  X-Page: about\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolve_headers(&rules, "/assets/js/app.js"),
+            vec![
+                ("vary".to_string(), "Accept, Origin".to_string()),
+                ("cache-control".to_string(), "immutable".to_string())
+            ]
+        );
+        assert_eq!(resolve_headers(&rules, "/about").len(), 2);
+        assert_eq!(resolve_headers(&rules, "/assets/app.css").len(), 1);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("/*", "/"));
+        assert!(glob_matches("/*.html", "/docs/index.html"));
+        assert!(glob_matches("/a/*/c", "/a/b/b/c"));
+        assert!(!glob_matches("/a/*/c", "/a/b/d"));
+        assert!(!glob_matches("/a", "/ab"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let rules = parse_redirects(
+            "/blog/:year/:month/* /posts/:year-:month/:splat 301\n\
+             /search q=:term /find?term=:term 302\n\
+             /docs https://docs.example.com/ 302\n\
+             /* /index.html 200\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolve(&rules, "/blog/2024/05/hello/world", None, false),
+            Some(RuleMatch::Redirect {
+                location: "/posts/2024-05/hello/world".to_string(),
+                status: 301
+            })
+        );
+        assert_eq!(
+            resolve(&rules, "/search", Some("q=rust"), false),
+            Some(RuleMatch::Redirect {
+                location: "/find?term=rust".to_string(),
+                status: 302
+            })
+        );
+        assert_eq!(
+            resolve(&rules, "/docs/", Some("a=b"), false),
+            Some(RuleMatch::Redirect {
+                location: "https://docs.example.com/?a=b".to_string(),
+                status: 302
+            })
+        );
+        assert_eq!(
+            resolve(&rules, "/settings/profile", None, false),
+            Some(RuleMatch::Rewrite {
+                path: "/index.html".to_string(),
+                status: 200
+            })
+        );
+
+        // existing files shadow rules that are not forced
+        assert_eq!(resolve(&rules, "/assets/app.js", None, true), None);
+    }
+
+    #[test]
+    fn test_resolve_decodes_path() {
+        let rules = parse_redirects("/café /menu 301
+/files/* /downloads/:splat 302").unwrap();
+
+        assert_eq!(
+            resolve(&rules, "/caf%C3%A9", None, false),
+            Some(RuleMatch::Redirect {
+                location: "/menu".to_string(),
+                status: 301
+            })
+        );
+        assert_eq!(
+            resolve(&rules, "/files/a%20b/r%C3%A9sum%C3%A9%3F.pdf", None, false),
+            Some(RuleMatch::Redirect {
+                location: "/downloads/a%20b/r%C3%A9sum%C3%A9%3F.pdf".to_string(),
+                status: 302
+            })
+        );
+    }
+
+    #[test]
+    fn test_forced_rules() {
+        let rules = parse_redirects("/index.html / 301!\n/:page /pages/:page 200").unwrap();
+
+        assert_eq!(
+            resolve(&rules, "/index.html", None, true),
+            Some(RuleMatch::Redirect {
+                location: "/".to_string(),
+                status: 301
+            })
+        );
+        assert_eq!(resolve(&rules, "/about", None, true), None);
+        assert_eq!(resolve(&rules, "/about/more", None, false), None);
+    }
+}
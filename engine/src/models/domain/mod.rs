@@ -8,6 +8,10 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::state::State;
 
+pub use self::name::{normalize_domain, normalize_host, DomainNameError};
+
+mod name;
+
 #[derive(
     Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, FromRow, Object,
 )]
@@ -15,6 +19,15 @@ pub struct Domain {
     pub site_id: String,
     pub domain: String,
     pub created_at: DateTime<Utc>,
+    /// The environment this domain serves, `None` serves the live deployment
+    pub environment: Option<String>,
+    /// The challenge the domain was verified with, `None` when it was added without verification
+    pub challenge: Option<String>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    /// Consecutive checks that found the domain no longer pointing at the engine
+    pub check_failures: i32,
+    /// Why the last check failed, set while the domain is flagged
+    pub failure_reason: Option<String>,
 }
 
 impl Domain {
@@ -116,9 +129,14 @@ impl Domain {
         .execute(&state.database.pool)
         .await?;
 
+        state.cache.invalidate_hosts();
+
         Ok(())
     }
 
+    /// Adds a domain to a site, or a pending domain when it overlaps existing domains
+    ///
+    /// `domain` is expected to be normalized with `normalize_domain`, the form domains are stored and matched in
     pub async fn create_for_site(
         site_id: &str,
         domain: &str,
@@ -176,9 +194,37 @@ impl Domain {
         .fetch_one(&state.database.pool)
         .await?;
 
+        state.cache.invalidate_hosts();
+
         Ok(domain.into())
     }
 
+    /// Binds the domain to an environment of its site, `None` makes it serve the live deployment again
+    pub async fn set_environment(
+        site_id: &str,
+        domain: &str,
+        environment: Option<&str>,
+        state: &State,
+    ) -> Result<Option<Domain>, Error> {
+        let span = info_span!("Domain::set_environment");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let domain = sqlx::query_as!(
+            Domain,
+            "UPDATE domains SET environment = $1 WHERE site_id = $2 AND domain = $3 RETURNING *",
+            environment,
+            site_id,
+            domain
+        )
+        .fetch_optional(&state.database.pool)
+        .await?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(domain)
+    }
+
     pub async fn create_for_site_superceded(
         site_id: &str,
         domain: &str,
@@ -192,9 +238,98 @@ impl Domain {
             .fetch_one(&state.database.pool)
             .await?;
 
+        state.cache.invalidate_hosts();
+
         Ok(domain)
     }
 
+    /// Active domains last checked before `cutoff`, never checked ones first
+    pub async fn due_for_recheck(
+        cutoff: DateTime<Utc>,
+        limit: i64,
+        state: &State,
+    ) -> Result<Vec<Domain>, Error> {
+        let span = info_span!("Domain::due_for_recheck");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let domains = sqlx::query_as!(
+            Domain,
+            "SELECT * FROM domains WHERE last_checked_at IS NULL OR last_checked_at < $1 ORDER BY last_checked_at NULLS FIRST LIMIT $2",
+            cutoff,
+            limit
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        Ok(domains)
+    }
+
+    /// Stores the outcome of a re-check, `None` clears the flag and the failure count
+    ///
+    /// Returns `None` when the domain was removed in the meantime
+    pub async fn record_check(
+        &self,
+        failure_reason: Option<&str>,
+        state: &State,
+    ) -> Result<Option<Domain>, Error> {
+        let span = info_span!("Domain::record_check");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let domain = sqlx::query_as!(
+            Domain,
+            r#"
+            UPDATE domains
+            SET last_checked_at = NOW(),
+                check_failures = CASE WHEN $1::TEXT IS NULL THEN 0 ELSE check_failures + 1 END,
+                failure_reason = $1
+            WHERE site_id = $2 AND domain = $3
+            RETURNING *
+            "#,
+            failure_reason,
+            self.site_id,
+            self.domain
+        )
+        .fetch_optional(&state.database.pool)
+        .await?;
+
+        Ok(domain)
+    }
+
+    /// Takes the domain from its site and turns it back into a pending domain with a new challenge
+    pub async fn detach(&self, failure_reason: &str, state: &State) -> Result<(), Error> {
+        let span = info_span!("Domain::detach");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let mut tx = state.database.pool.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM domains WHERE site_id = $1 AND domain = $2",
+            self.site_id,
+            self.domain
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO domains_pending (site_id, domain, challenge, status, failure_reason) VALUES ($1, $2, $3, 'failed', $4) ON CONFLICT (site_id, domain) DO NOTHING",
+            self.site_id,
+            self.domain,
+            uuid::Uuid::new_v4().to_string(),
+            failure_reason
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(())
+    }
+
     pub async fn existing_domain_by_name(
         domain: &str,
         state: &State,
@@ -210,6 +345,33 @@ impl Domain {
         Ok(domain)
     }
 
+    /// Resolves the domain entry responsible for serving the given host, normalized with `normalize_host`
+    ///
+    /// Given `hello.world.luc.computer` it will look for `hello.world.luc.computer`, `*.world.luc.computer`, `*.luc.computer` and `*.computer`
+    /// An exact match always wins, otherwise the most specific wildcard is returned
+    pub async fn resolve_by_host(
+        host: &str,
+        state: &State,
+    ) -> Result<Option<Domain>, Error> {
+        let span = info_span!("Domain::resolve_by_host");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let candidates = host_candidates(host);
+
+        let mut domains = sqlx::query_as!(
+            Domain,
+            "SELECT * FROM domains WHERE domain = ANY($1)",
+            &candidates
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        domains.sort_by_key(|x| (x.domain != host, std::cmp::Reverse(x.domain.len())));
+
+        Ok(domains.into_iter().next())
+    }
+
     /// Given a wildcard domain `*.luc.computer` it will return all downwards overlapping domains
     ///
     /// Given `*.luc.computer` it will return `['hello.world.luc.computer', '*.dev.luc.computer', '*.computer']`
@@ -252,7 +414,7 @@ impl Domain {
         span.set_parent(Context::current());
         let _guard = span.enter();
 
-        let domain = domain[2..].to_string();
+        let domain = domain.strip_prefix("*.").unwrap_or(domain);
 
         let domains = sqlx::query_as!(
             Domain,
@@ -273,12 +435,21 @@ pub struct DomainPending {
     pub site_id: String,
     pub domain: String,
     pub challenge: String,
+    /// `pending` until the first check, `failed` when the last check did not find the challenge
     pub status: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// How many times the challenge has been checked
+    pub attempts: i32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// Why the last check failed
+    pub failure_reason: Option<String>,
+    /// When the background verifier checks the challenge next
+    pub next_attempt_at: DateTime<Utc>,
 }
 
 impl DomainPending {
+    /// `domain` is expected to be normalized with `normalize_domain`
     pub async fn create(
         site_id: &str,
         domain: &str,
@@ -345,43 +516,168 @@ impl DomainPending {
 
         Ok(())
     }
-    pub async fn do_challenge(&self, state: &State) -> Result<(), Error> {
+
+    /// Pending domains whose next check is due, longest waiting first
+    pub async fn due(limit: i64, state: &State) -> Result<Vec<DomainPending>, Error> {
+        let span = info_span!("DomainPending::due");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let domains = sqlx::query_as!(
+            DomainPending,
+            "SELECT * FROM domains_pending WHERE next_attempt_at <= NOW() ORDER BY next_attempt_at LIMIT $1",
+            limit
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        Ok(domains)
+    }
+
+    pub async fn schedule_retry(
+        &self,
+        next_attempt_at: DateTime<Utc>,
+        state: &State,
+    ) -> Result<(), Error> {
+        let span = info_span!("DomainPending::schedule_retry");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        sqlx::query!(
+            "UPDATE domains_pending SET next_attempt_at = $1 WHERE site_id = $2 AND domain = $3",
+            next_attempt_at,
+            self.site_id,
+            self.domain
+        )
+        .execute(&state.database.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes the pending domains created before `cutoff`, their challenge was never completed
+    pub async fn expire_stale(
+        cutoff: DateTime<Utc>,
+        state: &State,
+    ) -> Result<Vec<DomainPending>, Error> {
+        let span = info_span!("DomainPending::expire_stale");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let expired = sqlx::query_as!(
+            DomainPending,
+            "DELETE FROM domains_pending WHERE created_at < $1 RETURNING *",
+            cutoff
+        )
+        .fetch_all(&state.database.pool)
+        .await?;
+
+        Ok(expired)
+    }
+
+    /// Checks the `_edgeserver-challenge` TXT record of the domain and promotes the pending domain once it holds the challenge
+    ///
+    /// Only after verification are the domains it supersedes taken from their sites, they get a pending entry of their own.
+    /// A failed check is recorded on the pending domain, which is returned as is.
+    /// Pending domains the site already serves are dropped in favour of the existing domain.
+    pub async fn do_challenge(&self, state: &State) -> Result<DomainSubmission, Error> {
         let span = info_span!("DomainPending::do_challenge");
         span.set_parent(Context::current());
         let _guard = span.enter();
 
-        // get the dns txt record `_edgeserver-challenge` and if its equal to the challenge, update the status to verified
+        // the site already serves the name (e.g. it was added again while verified), nothing is left to verify
+        if let Some(existing) = Domain::get_by_site_id_and_domain(&self.site_id, &self.domain, state).await? {
+            DomainPending::delete_by_site_id_and_domain(&self.site_id, &self.domain, state).await?;
+
+            return Ok(existing.into());
+        }
 
-        // if a site exists for this domain delete it and generate a challenge in its place
-        let existing_domain = Domain::existing_domain_by_name(&self.domain, state).await;
+        let record_name = challenge_record_name(&self.domain);
 
-        if existing_domain.is_ok() {
-            let domain = sqlx::query_as!(
-                Domain,
-                "DELETE FROM domains WHERE domain = $1 RETURNING *",
+        let failure_reason = match state.dns.txt_records(&record_name).await {
+            Ok(records) if records.iter().any(|record| record.trim() == self.challenge) => None,
+            Ok(records) if records.is_empty() => Some(format!("No TXT record found at {}", record_name)),
+            Ok(_) => Some(format!("The TXT record at {} does not match the challenge", record_name)),
+            Err(error) => Some(format!("Failed to look up {}: {}", record_name, error)),
+        };
+
+        if let Some(failure_reason) = failure_reason {
+            info!("Challenge failed for {}: {}", self.domain, failure_reason);
+
+            let pending = sqlx::query_as!(
+                DomainPending,
+                r#"
+                UPDATE domains_pending
+                SET status = 'failed', attempts = attempts + 1, last_attempt_at = NOW(), failure_reason = $1, updated_at = NOW()
+                WHERE site_id = $2 AND domain = $3
+                RETURNING *
+                "#,
+                failure_reason,
+                self.site_id,
                 self.domain
             )
             .fetch_one(&state.database.pool)
             .await?;
 
-            DomainPending::create(&domain.site_id, &domain.domain, state).await?;
+            return Ok(pending.into());
+        }
+
+        // the same name, or for wildcards every domain below it, is taken from the sites currently serving it
+        let superseded: Vec<Domain> = Domain::get_soft_overlap(&self.domain, state)
+            .await?
+            .into_iter()
+            .filter(|existing| existing.site_id != self.site_id)
+            .collect();
+
+        let mut tx = state.database.pool.begin().await?;
 
-            info!("Updated the superseded domain and created a new challenge for it");
+        for existing in &superseded {
+            sqlx::query!(
+                "DELETE FROM domains WHERE site_id = $1 AND domain = $2",
+                existing.site_id,
+                existing.domain
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                "INSERT INTO domains_pending (site_id, domain, challenge, status) VALUES ($1, $2, $3, 'pending') ON CONFLICT (site_id, domain) DO NOTHING",
+                existing.site_id,
+                existing.domain,
+                uuid::Uuid::new_v4().to_string()
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            info!(
+                "Domain {} superseded on site {}, created a new challenge for it",
+                existing.domain, existing.site_id
+            );
         }
 
+        let domain = sqlx::query_as!(
+            Domain,
+            "INSERT INTO domains (site_id, domain, challenge) VALUES ($1, $2, $3) RETURNING *",
+            self.site_id,
+            self.domain,
+            self.challenge
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
         sqlx::query!(
-            "UPDATE domains_pending SET status = 'pending' WHERE site_id = $1 AND domain = $2",
+            "DELETE FROM domains_pending WHERE site_id = $1 AND domain = $2",
             self.site_id,
             self.domain
         )
-        .execute(&state.database.pool)
+        .execute(&mut *tx)
         .await?;
 
-        // mark this domainpending as verified and create a new domain in its place
-        let _domain =
-            Domain::create_for_site(&self.site_id, &self.domain, state).await?;
+        tx.commit().await?;
 
-        Ok(())
+        state.cache.invalidate_hosts();
+
+        Ok(domain.into())
     }
 }
 
@@ -411,6 +707,31 @@ impl DomainSubmission {
         }
     }
 }
+/// The name the TXT record holding the challenge of a domain is looked up at, wildcards are verified at their parent
+///
+/// Given `*.luc.computer` it will return `_edgeserver-challenge.luc.computer`
+pub fn challenge_record_name(domain: &str) -> String {
+    format!(
+        "_edgeserver-challenge.{}",
+        domain.strip_prefix("*.").unwrap_or(domain)
+    )
+}
+
+/// Lists the domain entries that could serve a host, the host itself followed by every wildcard above it
+///
+/// Given `hello.luc.computer` it will return `['hello.luc.computer', '*.luc.computer', '*.computer']`
+pub fn host_candidates(host: &str) -> Vec<String> {
+    let mut candidates = vec![host.to_string()];
+    let mut rest = host;
+
+    while let Some((_, parent)) = rest.split_once('.') {
+        candidates.push(format!("*.{}", parent));
+        rest = parent;
+    }
+
+    candidates
+}
+
 /// Sorts domains in reverse order (TLD first), with "*" treated as coming last
 fn sort_domains_by_reversed_parts(a: &str, b: &str) -> std::cmp::Ordering {
     // Split domains by dots and reverse the parts
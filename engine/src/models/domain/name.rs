@@ -0,0 +1,96 @@
+/// Why a submitted domain name was refused
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum DomainNameError {
+    #[error("Domain is empty")]
+    Empty,
+    #[error("Wildcards are only allowed as the first label, like *.luc.computer")]
+    MisplacedWildcard,
+    #[error("{0:?} is not a valid domain name")]
+    Invalid(String),
+    #[error("{0} is a public suffix, only names registered below it can be added")]
+    PublicSuffix(String),
+}
+
+/// Brings a submitted domain into the form it is stored and looked up in
+///
+/// Trims it, drops the trailing dot, lowercases it and converts internationalized labels to punycode (`Bücher.Example.` becomes `xn--bcher-kva.example`).
+/// A leading `*.` is kept, but the name (below the wildcard) has to be registrable, so bare TLDs, `co.uk` or `*.com` are refused
+pub fn normalize_domain(domain: &str) -> Result<String, DomainNameError> {
+    let domain = domain.trim();
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+
+    if domain.is_empty() {
+        return Err(DomainNameError::Empty);
+    }
+
+    let (wildcard, name) = match domain.strip_prefix("*.") {
+        Some(name) => (true, name),
+        None => (false, domain),
+    };
+
+    if name.contains('*') {
+        return Err(DomainNameError::MisplacedWildcard);
+    }
+
+    // strict conversion also enforces hostname characters and label lengths, and refuses empty labels
+    let name = idna::domain_to_ascii_strict(name).map_err(|_| DomainNameError::Invalid(domain.to_string()))?;
+
+    if psl::domain(name.as_bytes()).is_none() {
+        return Err(DomainNameError::PublicSuffix(name));
+    }
+
+    Ok(match wildcard {
+        true => format!("*.{}", name),
+        false => name,
+    })
+}
+
+/// Normalizes the host a request was made to the same way, without refusing any names, `None` if it isn't a hostname
+pub fn normalize_host(host: &str) -> Option<String> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+
+    if host.is_empty() {
+        return None;
+    }
+
+    idna::domain_to_ascii(host).ok().filter(|host| !host.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain(" Luc.Computer. "), Ok("luc.computer".to_string()));
+        assert_eq!(normalize_domain("*.LUC.computer"), Ok("*.luc.computer".to_string()));
+        assert_eq!(normalize_domain("bücher.example"), Ok("xn--bcher-kva.example".to_string()));
+        assert_eq!(normalize_domain("*.Bücher.example"), Ok("*.xn--bcher-kva.example".to_string()));
+        assert_eq!(normalize_domain("xn--bcher-kva.example"), Ok("xn--bcher-kva.example".to_string()));
+        assert_eq!(normalize_domain("hello.example.co.uk"), Ok("hello.example.co.uk".to_string()));
+
+        assert_eq!(normalize_domain(" "), Err(DomainNameError::Empty));
+        assert_eq!(normalize_domain("a.*.luc.computer"), Err(DomainNameError::MisplacedWildcard));
+        assert!(matches!(normalize_domain("-luc.computer"), Err(DomainNameError::Invalid(_))));
+        assert!(matches!(normalize_domain("luc computer.com"), Err(DomainNameError::Invalid(_))));
+        assert!(matches!(normalize_domain("luc..computer"), Err(DomainNameError::Invalid(_))));
+        assert!(matches!(normalize_domain(&format!("{}.com", "a".repeat(64))), Err(DomainNameError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_normalize_domain_public_suffix() {
+        assert_eq!(normalize_domain("com"), Err(DomainNameError::PublicSuffix("com".to_string())));
+        assert_eq!(normalize_domain("*.com"), Err(DomainNameError::PublicSuffix("com".to_string())));
+        assert_eq!(normalize_domain("CO.UK"), Err(DomainNameError::PublicSuffix("co.uk".to_string())));
+        assert_eq!(normalize_domain("*.github.io"), Err(DomainNameError::PublicSuffix("github.io".to_string())));
+        assert_eq!(normalize_domain("luc.github.io"), Ok("luc.github.io".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("Hello.Luc.Computer."), Some("hello.luc.computer".to_string()));
+        assert_eq!(normalize_host("bücher.example"), Some("xn--bcher-kva.example".to_string()));
+        assert_eq!(normalize_host("localhost"), Some("localhost".to_string()));
+        assert_eq!(normalize_host("."), None);
+    }
+}
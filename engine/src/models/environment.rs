@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use opentelemetry::Context;
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as};
+use tracing::info_span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::database::Database;
+
+use super::deployment::Deployment;
+
+/// A named pointer at one of the deployments of a site, e.g. `staging` or `pr-123`
+///
+/// Domains bound to an environment serve its deployment instead of the live one
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SiteEnvironment {
+    pub site_id: String,
+    pub name: String,
+    pub deployment_id: Option<String>,
+    /// The branch this environment follows, new deployments of it move the environment along
+    pub branch: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SiteEnvironment {
+    pub async fn get_by_site_id(db: &Database, site_id: &str) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("SiteEnvironment::get_by_site_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            SiteEnvironment,
+            "SELECT * FROM site_environments WHERE site_id = $1 ORDER BY name",
+            site_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    /// The environments currently serving a deployment
+    pub async fn get_by_deployment_id(db: &Database, deployment_id: &str) -> Result<Vec<Self>, sqlx::Error> {
+        let span = info_span!("SiteEnvironment::get_by_deployment_id");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            SiteEnvironment,
+            "SELECT * FROM site_environments WHERE deployment_id = $1 ORDER BY name",
+            deployment_id
+        )
+        .fetch_all(&db.pool)
+        .await
+    }
+
+    pub async fn get_by_name(
+        db: &Database,
+        site_id: &str,
+        name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let span = info_span!("SiteEnvironment::get_by_name");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            SiteEnvironment,
+            "SELECT * FROM site_environments WHERE site_id = $1 AND name = $2",
+            site_id,
+            name
+        )
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    /// Creates the environment or points an existing one at another deployment
+    pub async fn set_deployment(
+        db: &Database,
+        site_id: &str,
+        name: &str,
+        deployment_id: &str,
+        branch: Option<&str>,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("SiteEnvironment::set_deployment");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            SiteEnvironment,
+            r#"
+            INSERT INTO site_environments (site_id, name, deployment_id, branch)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (site_id, name) DO UPDATE
+            SET deployment_id = EXCLUDED.deployment_id,
+                branch = COALESCE(EXCLUDED.branch, site_environments.branch),
+                updated_at = NOW()
+            RETURNING *
+            "#,
+            site_id,
+            name,
+            deployment_id,
+            branch
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// Removes the environment, domains bound to it go back to serving the live deployment
+    pub async fn delete(db: &Database, site_id: &str, name: &str) -> Result<bool, sqlx::Error> {
+        let span = info_span!("SiteEnvironment::delete");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        let mut tx = db.pool.begin().await?;
+
+        query!(
+            "UPDATE domains SET environment = NULL WHERE site_id = $1 AND environment = $2",
+            site_id,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let deleted = query!(
+            "DELETE FROM site_environments WHERE site_id = $1 AND name = $2",
+            site_id,
+            name
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+
+    /// Moves the environment of a freshly uploaded deployment to it
+    ///
+    /// The environment is named after the `environment` label of the deployment, or derived from its branch
+    /// (`feature/login` becomes `feature-login`). Deployments without either are left alone.
+    pub async fn track_deployment(
+        db: &Database,
+        deployment: &Deployment,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let name = deployment
+            .environment
+            .clone()
+            .or_else(|| deployment.branch.as_deref().and_then(environment_name_for_branch));
+
+        let Some(name) = name else {
+            return Ok(None);
+        };
+
+        SiteEnvironment::set_deployment(
+            db,
+            &deployment.site_id,
+            &name,
+            &deployment.deployment_id,
+            deployment.branch.as_deref(),
+        )
+        .await
+        .map(Some)
+    }
+}
+
+/// Derives an environment name from a branch, lowercase letters, digits and dashes
+pub fn environment_name_for_branch(branch: &str) -> Option<String> {
+    let mut name = String::with_capacity(branch.len());
+
+    for c in branch.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+
+    name.truncate(63);
+    let name = name.trim_end_matches('-');
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_name_for_branch() {
+        assert_eq!(environment_name_for_branch("main"), Some("main".to_string()));
+        assert_eq!(environment_name_for_branch("feature/Login_Page"), Some("feature-login-page".to_string()));
+        assert_eq!(environment_name_for_branch("--fix--"), Some("fix".to_string()));
+        assert_eq!(environment_name_for_branch("///"), None);
+    }
+}
@@ -4,4 +4,7 @@ pub mod deployment;
 pub mod team;
 pub mod session;
 pub mod domain;
+pub mod environment;
 pub mod keys;
+pub mod access;
+pub mod certificate;
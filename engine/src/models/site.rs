@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use opentelemetry::Context;
-use poem_openapi::Object;
+use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, query_scalar};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
@@ -22,6 +22,52 @@ pub struct Site {
     pub team_id: String,
     pub name: String,
     pub created_at: DateTime<Utc>,
+    /// The deployment that is currently live
+    pub active_deployment_id: Option<String>,
+    /// Always keep the newest N deployments
+    pub retention_keep_last: Option<i32>,
+    /// Always keep deployments younger than N days
+    pub retention_days: Option<i32>,
+    /// Serve `/about` from `about.html` and redirect `/about.html` to `/about`
+    pub clean_urls: bool,
+    /// `ignore`, `always` or `never`, see `TrailingSlash`
+    pub trailing_slash: String,
+    /// The file served for directory paths, e.g. `index.html`
+    pub index_file: String,
+    /// Serve the root index file with status 200 for paths that don't exist, instead of the 404 page
+    pub spa_mode: bool,
+}
+
+/// Whether paths that resolve to a directory index or `.html` file should end in a slash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+#[oai(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Serve both `/about` and `/about/`
+    Ignore,
+    /// Redirect `/about` to `/about/`
+    Always,
+    /// Redirect `/about/` to `/about`
+    Never,
+}
+
+impl TrailingSlash {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrailingSlash::Ignore => "ignore",
+            TrailingSlash::Always => "always",
+            TrailingSlash::Never => "never",
+        }
+    }
+
+    /// Parses the `sites.trailing_slash` column, unknown values are treated as `ignore`
+    pub fn parse(trailing_slash: &str) -> Self {
+        match trailing_slash {
+            "always" => TrailingSlash::Always,
+            "never" => TrailingSlash::Never,
+            _ => TrailingSlash::Ignore,
+        }
+    }
 }
 
 impl Site {
@@ -114,6 +160,7 @@ impl Site {
     pub async fn get_deployments(
         db: &Database,
         site_id: impl AsRef<str>,
+        filter: &DeploymentFilter,
     ) -> Result<Vec<Deployment>, sqlx::Error> {
         let span = info_span!("Site::get_deployments");
         span.set_parent(Context::current());
@@ -121,13 +168,75 @@ impl Site {
 
         query_as!(
             Deployment,
-            "SELECT * FROM deployments WHERE site_id = $1 ORDER BY created_at DESC",
-            site_id.as_ref()
+            r#"
+            SELECT * FROM deployments
+            WHERE site_id = $1
+            AND ($2::text IS NULL OR branch = $2)
+            AND ($3::text IS NULL OR commit_sha LIKE $3 || '%')
+            AND ($4::text IS NULL OR environment = $4)
+            ORDER BY created_at DESC
+            "#,
+            site_id.as_ref(),
+            filter.branch,
+            filter.commit_sha.as_ref().map(|sha| sha.to_lowercase()),
+            filter.environment
         )
         .fetch_all(&db.pool)
         .await
     }
 
+    /// Sets the retention policy, `None` for both keeps deployments forever
+    pub async fn update_retention(
+        db: &Database,
+        site_id: impl AsRef<str>,
+        keep_last: Option<i32>,
+        days: Option<i32>,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("Site::update_retention");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Site,
+            "UPDATE sites SET retention_keep_last = $1, retention_days = $2 WHERE site_id = $3 RETURNING *",
+            keep_last,
+            days,
+            site_id.as_ref()
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
+    /// Sets how request paths are resolved to the files of the site's deployments
+    pub async fn update_serving(
+        db: &Database,
+        site_id: impl AsRef<str>,
+        clean_urls: bool,
+        trailing_slash: TrailingSlash,
+        index_file: &str,
+        spa_mode: bool,
+    ) -> Result<Self, sqlx::Error> {
+        let span = info_span!("Site::update_serving");
+        span.set_parent(Context::current());
+        let _guard = span.enter();
+
+        query_as!(
+            Site,
+            r#"
+            UPDATE sites SET clean_urls = $1, trailing_slash = $2, index_file = $3, spa_mode = $4
+            WHERE site_id = $5
+            RETURNING *
+            "#,
+            clean_urls,
+            trailing_slash.as_str(),
+            index_file,
+            spa_mode,
+            site_id.as_ref()
+        )
+        .fetch_one(&db.pool)
+        .await
+    }
+
     pub async fn update_team(
         db: &Database,
         site_id: impl AsRef<str>,
@@ -147,6 +256,16 @@ impl Site {
         .map(|_| ())
     }
 }
+
+/// Narrows down `Site::get_deployments`, fields that are `None` match everything
+#[derive(Debug, Default)]
+pub struct DeploymentFilter {
+    pub branch: Option<String>,
+    /// Matches full or abbreviated shas
+    pub commit_sha: Option<String>,
+    pub environment: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct SiteId<'a>(pub &'a str);
 
@@ -22,6 +22,12 @@ pub enum HttpError {
 
     #[error("Unauthorized")]
     Unauthorized,
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
 }
 
 impl ResponseError for HttpError {
@@ -29,9 +35,14 @@ impl ResponseError for HttpError {
     where
         Self: std::error::Error + Send + Sync + 'static,
     {
-        poem::Response::default()
-            .with_status(self.status())
-            .into_response()
+        match self {
+            HttpError::BadRequest(message) | HttpError::Conflict(message) => poem::Response::builder()
+                .status(self.status())
+                .body(message.clone()),
+            _ => poem::Response::default()
+                .with_status(self.status())
+                .into_response(),
+        }
     }
     fn status(&self) -> StatusCode {
         match self {
@@ -46,10 +57,9 @@ impl ResponseError for HttpError {
                 StatusCode::INTERNAL_SERVER_ERROR
             },
             HttpError::Unauthorized => StatusCode::UNAUTHORIZED,
-            other => {
-                error!("Unknown error: {:?}", other);
-                StatusCode::BAD_REQUEST
-            }
+            HttpError::Forbidden => StatusCode::FORBIDDEN,
+            HttpError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            HttpError::Conflict(_) => StatusCode::CONFLICT,
         }
     }
 }
@@ -7,7 +7,7 @@ use poem::middleware::OpenTelemetryMetrics;
 use poem::web::Data;
 use poem::Response;
 use poem::{
-    endpoint::StaticFilesEndpoint, get, handler, listener::TcpListener, middleware::Cors,
+    endpoint::StaticFilesEndpoint, get, handler, listener::{Listener, TcpListener}, middleware::Cors,
     web::Html, EndpointExt, Route, Server,
 };
 use poem_openapi::{OpenApi, OpenApiService, Tags};
@@ -17,10 +17,12 @@ use user::UserApi;
 
 use crate::middlewares::tracing::TraceId;
 use crate::state::State;
+use crate::tls::TlsListener;
 
 pub mod auth;
 pub mod error;
 pub mod invite;
+pub mod serve;
 pub mod site;
 pub mod team;
 pub mod user;
@@ -182,12 +184,17 @@ pub async fn serve(state: State) {
         .index_file("index.html")
         .fallback_to_index();
 
+    // certificates are issued by the acme worker, without it there is nothing to serve
+    let tls_bind = state.config.acme.as_ref().map(|acme| acme.tls_bind.clone());
+    let tls = state.tls.clone();
+
     let app = Route::new()
         .nest("/api", api_service)
         .nest("/openapi.json", openapi_route)
         .at("/docs", get(get_openapi_docs))
         .nest("/", file_endpoint)
         .with(Cors::new())
+        .with(serve::SiteServer::new())
         .with(TraceId::new(Arc::new(global::tracer("edgeserver"))))
         // .with(OpenTelemetryTracing::new(global::tracer("edgeserver")))
         // .with(Tracing::default())
@@ -196,7 +203,16 @@ pub async fn serve(state: State) {
 
     let listener = TcpListener::bind("0.0.0.0:3000");
 
-    Server::new(listener).run(app).await.unwrap()
+    match tls_bind {
+        Some(tls_bind) => {
+            info!("Serving HTTPS on {}", tls_bind);
+
+            let tls_listener = TlsListener::new(TcpListener::bind(tls_bind), tls);
+
+            Server::new(listener.combine(tls_listener)).run(app).await.unwrap()
+        }
+        None => Server::new(listener).run(app).await.unwrap(),
+    }
 }
 
 #[handler]
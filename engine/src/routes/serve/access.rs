@@ -0,0 +1,291 @@
+use std::net::IpAddr;
+
+use chrono::Utc;
+use poem::{
+    http::{header, uri::Scheme, Method, StatusCode},
+    web::{
+        headers::{authorization::Basic, Authorization, HeaderMapExt},
+        RealIp,
+    },
+    FromRequest, Request, Response, Result,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{cache::ACCESS_FAILURES_WINDOW, models::access::SiteAccess, state::State};
+
+/// Set once the password page was passed, signed with the `cookie_secret` of the access controls
+const ACCESS_COOKIE: &str = "edgeserver_access";
+/// How long the password page lets a visitor in
+const ACCESS_COOKIE_MAX_AGE: i64 = 7 * 24 * 60 * 60;
+/// Largest password page submission that is read
+const MAX_FORM_SIZE: usize = 16 * 1024;
+/// Wrong credentials a client may try on a site before it is turned away, counted in `Cache.access_failures`
+const MAX_FAILED_ATTEMPTS: u32 = 10;
+
+const PASSWORD_PAGE: &str = include_str!("../password.html");
+
+/// Checks a request against the access controls of the site it is for
+///
+/// Returns the response to answer with when the request may not see the site, `None` lets it through.
+/// The client address is checked first, then basic auth and finally the password page, whose form posts to the requested path.
+pub async fn enforce(state: &State, access: &SiteAccess, req: &mut Request) -> Result<Option<Response>> {
+    if !access.allowed_ips.is_empty() {
+        let allowed = client_ip(state, req)
+            .await
+            .is_some_and(|ip| access.allows_ip(ip));
+
+        if !allowed {
+            return Ok(Some(
+                Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .header(header::CACHE_CONTROL, "no-store")
+                    .body("Forbidden"),
+            ));
+        }
+    }
+
+    if access.basic_auth_password_hash.is_some() {
+        let verification = match req.headers().typed_get::<Authorization<Basic>>() {
+            Some(credentials) => {
+                let grant = format!("basic:{}:{}", credentials.username(), credentials.password());
+                let (username, password) = (credentials.username().to_string(), credentials.password().to_string());
+                let client = client_ip(state, req).await;
+
+                verify_cached(state, access, client, &grant, move |access| {
+                    access.verify_basic_auth(&username, &password)
+                })
+                .await
+            }
+            None => Verification::Denied,
+        };
+
+        if verification == Verification::Throttled {
+            return Ok(Some(too_many_attempts()));
+        }
+
+        if verification == Verification::Denied {
+            return Ok(Some(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(header::WWW_AUTHENTICATE, "Basic realm=\"edgeserver\", charset=\"UTF-8\"")
+                    .header(header::CACHE_CONTROL, "no-store")
+                    .body("Unauthorized"),
+            ));
+        }
+    }
+
+    if access.password_hash.is_some() {
+        let now = Utc::now().timestamp();
+
+        let has_cookie = request_cookie(req, ACCESS_COOKIE).is_some_and(|value| access.verify_cookie(value, now));
+
+        if has_cookie {
+            return Ok(None);
+        }
+
+        if req.method() != Method::POST {
+            return Ok(Some(password_page(StatusCode::UNAUTHORIZED, "", req.method() == Method::HEAD)));
+        }
+
+        let form = req.take_body().into_bytes_limit(MAX_FORM_SIZE).await?;
+        let password = form_value(&form, "password").unwrap_or_default();
+
+        let grant = format!("password:{}", password);
+        let client = client_ip(state, req).await;
+        let verification =
+            verify_cached(state, access, client, &grant, move |access| access.verify_password(&password)).await;
+
+        if verification == Verification::Throttled {
+            return Ok(Some(too_many_attempts()));
+        }
+
+        if verification == Verification::Denied {
+            return Ok(Some(password_page(StatusCode::UNAUTHORIZED, "Incorrect password", false)));
+        }
+
+        let mut cookie = format!(
+            "{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax",
+            ACCESS_COOKIE,
+            access.sign_cookie(now + ACCESS_COOKIE_MAX_AGE),
+            ACCESS_COOKIE_MAX_AGE
+        );
+
+        if is_https(state, req) {
+            cookie.push_str("; Secure");
+        }
+
+        let location = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_else(|| "/".to_string());
+
+        // see other, so reloading the page doesn't resubmit the form
+        return Ok(Some(
+            Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(header::SET_COOKIE, cookie)
+                .header(header::LOCATION, location)
+                .finish(),
+        ));
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verification {
+    Granted,
+    Denied,
+    /// The client failed too often recently, the credentials weren't checked
+    Throttled,
+}
+
+/// Runs an argon2 verification once per set of credentials, successful ones are remembered in `Cache.access_grants`
+///
+/// The `cookie_secret` is part of the key, so changing the access controls forgets every grant.
+/// Verifications run on the blocking thread pool, and every attempt counts against the client until one succeeds,
+/// so a client guessing passwords is throttled after `MAX_FAILED_ATTEMPTS` before it can tie up more of it
+async fn verify_cached(
+    state: &State,
+    access: &SiteAccess,
+    client: Option<IpAddr>,
+    grant: &str,
+    verify: impl FnOnce(&SiteAccess) -> bool + Send + 'static,
+) -> Verification {
+    let mut hasher = Sha256::new();
+    hasher.update(access.cookie_secret.as_bytes());
+    hasher.update(grant.as_bytes());
+    let key = hex::encode(hasher.finalize());
+
+    if state.cache.access_grants.contains_key(&key) {
+        return Verification::Granted;
+    }
+
+    let client = match client {
+        Some(ip) => format!("{}:{}", access.site_id, ip),
+        None => format!("{}:unknown", access.site_id),
+    };
+
+    let attempts = state.cache.access_failures.get(&client).await.unwrap_or(0);
+
+    if attempts >= MAX_FAILED_ATTEMPTS {
+        return Verification::Throttled;
+    }
+
+    // counted before verifying, so concurrent guesses are limited too
+    state.cache.access_failures.insert(client.clone(), attempts + 1).await;
+
+    let access = access.clone();
+    let verified = async_std::task::spawn_blocking(move || verify(&access)).await;
+
+    if !verified {
+        return Verification::Denied;
+    }
+
+    state.cache.access_failures.invalidate(&client).await;
+    state.cache.access_grants.insert(key, ()).await;
+
+    Verification::Granted
+}
+
+fn too_many_attempts() -> Response {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, ACCESS_FAILURES_WINDOW.as_secs())
+        .header(header::CACHE_CONTROL, "no-store")
+        .body("Too many attempts, try again later")
+}
+
+fn password_page(status: StatusCode, error: &str, head_only: bool) -> Response {
+    let page = PASSWORD_PAGE.replace("{{error}}", error);
+
+    let response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store");
+
+    if head_only {
+        return response.header(header::CONTENT_LENGTH, page.len()).finish();
+    }
+
+    response.body(page)
+}
+
+/// The address the request came from, forwarding headers are only honored when the engine runs behind a trusted proxy
+async fn client_ip(state: &State, req: &Request) -> Option<IpAddr> {
+    if trust_proxy_headers(state) {
+        if let Ok(RealIp(Some(ip))) = RealIp::from_request_without_body(req).await {
+            return Some(ip.to_canonical());
+        }
+    }
+
+    req.remote_addr()
+        .as_socket_addr()
+        .map(|addr| addr.ip().to_canonical())
+}
+
+fn is_https(state: &State, req: &Request) -> bool {
+    if *req.scheme() == Scheme::HTTPS {
+        return true;
+    }
+
+    trust_proxy_headers(state)
+        && req
+            .headers()
+            .get("x-forwarded-proto")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+fn trust_proxy_headers(state: &State) -> bool {
+    state
+        .config
+        .access
+        .as_ref()
+        .is_some_and(|access| access.trust_proxy_headers)
+}
+
+fn request_cookie<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|cookies| cookie_value(cookies, name))
+}
+
+/// Finds a cookie in a `Cookie` header
+fn cookie_value<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies.split(';').find_map(|cookie| {
+        let (key, value) = cookie.trim().split_once('=')?;
+
+        (key == name).then(|| value.trim_matches('"'))
+    })
+}
+
+/// Finds a field in an `application/x-www-form-urlencoded` body
+fn form_value(form: &[u8], name: &str) -> Option<String> {
+    url::form_urlencoded::parse(form)
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_value() {
+        assert_eq!(cookie_value("a=1; edgeserver_access=123.abc", ACCESS_COOKIE), Some("123.abc"));
+        assert_eq!(cookie_value("edgeserver_access=\"1.a\"", ACCESS_COOKIE), Some("1.a"));
+        assert_eq!(cookie_value("other_edgeserver_access=1", ACCESS_COOKIE), None);
+        assert_eq!(cookie_value("", ACCESS_COOKIE), None);
+    }
+
+    #[test]
+    fn test_form_value() {
+        assert_eq!(form_value(b"password=hunter%202&x=1", "password"), Some("hunter 2".to_string()));
+        assert_eq!(form_value(b"password=a+b", "password"), Some("a b".to_string()));
+        assert_eq!(form_value(b"x=1", "password"), None);
+    }
+}
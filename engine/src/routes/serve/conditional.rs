@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+
+/// A strong ETag, the sha256 of the file contents
+pub fn etag(file_hash: &str) -> String {
+    format!("\"{}\"", file_hash)
+}
+
+/// Formats a timestamp as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Whether a GET can be answered with `304 Not Modified`
+///
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only looked at when it is absent
+pub fn is_not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: &str,
+    last_modified: DateTime<Utc>,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        return if_none_match.split(',').map(str::trim).any(|candidate| {
+            candidate == "*" || candidate.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        });
+    }
+
+    match if_modified_since.and_then(parse_http_date) {
+        Some(since) => last_modified.timestamp() <= since.timestamp(),
+        None => false,
+    }
+}
+
+/// The part of a file a request asks for
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// The whole file, also used for ranges that can't be parsed or span several parts
+    Full,
+    /// Bytes `start..=end`
+    Partial { start: u64, end: u64 },
+    /// The range starts beyond the end of the file
+    Unsatisfiable,
+}
+
+/// Interprets the `Range` and `If-Range` headers for a file of `size` bytes
+///
+/// Only single `bytes` ranges are supported, a failed `If-Range` check serves the whole file
+pub fn byte_range(
+    range: Option<&str>,
+    if_range: Option<&str>,
+    etag: &str,
+    last_modified: DateTime<Utc>,
+    size: u64,
+) -> ByteRange {
+    let Some(range) = range else {
+        return ByteRange::Full;
+    };
+
+    if let Some(if_range) = if_range.map(str::trim) {
+        let fresh = match parse_http_date(if_range) {
+            Some(date) => date.timestamp() == last_modified.timestamp(),
+            // weak etags never match
+            None => if_range == etag,
+        };
+
+        if !fresh {
+            return ByteRange::Full;
+        }
+    }
+
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        // bytes=-500, the last 500 bytes
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 || size == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+
+            ByteRange::Partial {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            }
+        }
+        // bytes=500-
+        (Some(start), None) if end.is_empty() => {
+            if start >= size {
+                return ByteRange::Unsatisfiable;
+            }
+
+            ByteRange::Partial { start, end: size - 1 }
+        }
+        // bytes=500-999
+        (Some(start), Some(end)) if start <= end => {
+            if start >= size {
+                return ByteRange::Unsatisfiable;
+            }
+
+            ByteRange::Partial {
+                start,
+                end: end.min(size - 1),
+            }
+        }
+        _ => ByteRange::Full,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_http_date() {
+        let date = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+
+        assert_eq!(http_date(date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let etag = etag("abc");
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        assert!(is_not_modified(Some("\"abc\""), None, &etag, date));
+        assert!(is_not_modified(Some("\"x\", W/\"abc\""), None, &etag, date));
+        assert!(is_not_modified(Some("*"), None, &etag, date));
+        assert!(!is_not_modified(Some("\"x\""), Some("Mon, 01 Jan 2024 00:00:00 GMT"), &etag, date));
+
+        assert!(is_not_modified(None, Some("Mon, 01 Jan 2024 00:00:00 GMT"), &etag, date));
+        assert!(!is_not_modified(None, Some("Sun, 31 Dec 2023 00:00:00 GMT"), &etag, date));
+        assert!(!is_not_modified(None, None, &etag, date));
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let etag = etag("abc");
+        let date = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let range = |range: &str| byte_range(Some(range), None, &etag, date, 1000);
+
+        assert_eq!(range("bytes=0-499"), ByteRange::Partial { start: 0, end: 499 });
+        assert_eq!(range("bytes=500-"), ByteRange::Partial { start: 500, end: 999 });
+        assert_eq!(range("bytes=-100"), ByteRange::Partial { start: 900, end: 999 });
+        assert_eq!(range("bytes=900-5000"), ByteRange::Partial { start: 900, end: 999 });
+        assert_eq!(range("bytes=1000-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-1,5-6"), ByteRange::Full);
+        assert_eq!(range("bytes=5-1"), ByteRange::Full);
+        assert_eq!(range("items=0-1"), ByteRange::Full);
+
+        assert_eq!(
+            byte_range(Some("bytes=0-1"), Some("\"abc\""), &etag, date, 1000),
+            ByteRange::Partial { start: 0, end: 1 }
+        );
+        assert_eq!(
+            byte_range(Some("bytes=0-1"), Some("\"old\""), &etag, date, 1000),
+            ByteRange::Full
+        );
+        assert_eq!(
+            byte_range(Some("bytes=0-1"), Some("Mon, 01 Jan 2024 00:00:00 GMT"), &etag, date, 1000),
+            ByteRange::Partial { start: 0, end: 1 }
+        );
+    }
+}
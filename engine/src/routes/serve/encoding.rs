@@ -0,0 +1,65 @@
+/// Picks the content coding to serve from the `Accept-Encoding` header
+///
+/// `available` is in order of preference, which breaks ties between equal quality values.
+/// Returns `None` when the identity (uncompressed) representation should be served.
+pub fn negotiate<'a>(accept_encoding: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let accept_encoding = accept_encoding?;
+
+    let mut accepted: Vec<(&str, f32)> = Vec::new();
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default();
+
+        if coding.is_empty() {
+            continue;
+        }
+
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        accepted.push((coding, quality));
+    }
+
+    let quality_of = |coding: &str| {
+        accepted
+            .iter()
+            .find(|(accepted, _)| accepted.eq_ignore_ascii_case(coding))
+            .or_else(|| accepted.iter().find(|(accepted, _)| *accepted == "*"))
+            .map(|(_, quality)| *quality)
+            .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(&'a str, f32)> = None;
+
+    for coding in available {
+        let quality = quality_of(coding);
+
+        if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+            best = Some((coding, quality));
+        }
+    }
+
+    best.map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVAILABLE: [&str; 2] = ["br", "gzip"];
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(Some("gzip, deflate, br, zstd"), &AVAILABLE), Some("br"));
+        assert_eq!(negotiate(Some("gzip"), &AVAILABLE), Some("gzip"));
+        assert_eq!(negotiate(Some("br;q=0.5, gzip;q=0.8"), &AVAILABLE), Some("gzip"));
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0"), &AVAILABLE), None);
+        assert_eq!(negotiate(Some("*"), &AVAILABLE), Some("br"));
+        assert_eq!(negotiate(Some("identity"), &AVAILABLE), None);
+        assert_eq!(negotiate(None, &AVAILABLE), None);
+        assert_eq!(negotiate(Some("br"), &["gzip"]), None);
+    }
+}
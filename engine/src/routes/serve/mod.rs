@@ -0,0 +1,681 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use color_eyre::eyre::eyre;
+use futures::{channel::mpsc, AsyncWrite, SinkExt, TryStreamExt};
+use poem::{
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    Body, Endpoint, IntoResponse, Middleware, Request, Response, Result,
+};
+use tracing::{info, warn};
+
+use crate::{
+    assets::variants::{self, FileVariant},
+    cache::{HostTarget, ServedDeployment},
+    models::{
+        access::SiteAccess,
+        certificate::AcmeHttpChallenge,
+        deployment::{
+            preview::deployment_id_from_preview_host,
+            rules::{self, DeploymentRules, RuleMatch},
+            Deployment, DeploymentFile, DeploymentFileEntry,
+        },
+        domain::{normalize_host, Domain},
+        environment::SiteEnvironment,
+        site::Site,
+    },
+    routes::error::HttpError,
+    state::State,
+};
+
+use conditional::ByteRange;
+use resolve::{CandidateKind, ServingOptions};
+
+pub mod access;
+pub mod conditional;
+pub mod encoding;
+pub mod resolve;
+
+/// Middleware that serves deployed sites.
+///
+/// Requests whose `Host` resolves to a domain in the `domains` table are answered from the
+/// site's deployment, every other request falls through to the wrapped endpoint (api & frontend).
+/// Preview hostnames under the configured preview base domain serve the deployment they name.
+/// The `_redirects`, `_headers` and `edgeserver.json` rules of the deployment are applied to every response apart from redirects.
+/// Sites with access controls only serve allowed addresses and ask for credentials or a password first.
+#[derive(Default)]
+pub struct SiteServer;
+
+impl SiteServer {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for SiteServer {
+    type Output = SiteServerEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        SiteServerEndpoint { inner: ep }
+    }
+}
+
+/// The endpoint wrapper produced by the SiteServer middleware.
+pub struct SiteServerEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for SiteServerEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        let state = req.data::<State>().cloned();
+        let host = request_host(&req);
+
+        if let (Some(state), Some(host)) = (state, host) {
+            if let Some(response) = acme_challenge(&state, &req).await? {
+                return Ok(response);
+            }
+
+            if let Some(deployment_id) = preview_deployment_id(&state, &host) {
+                return serve_preview(&state, &deployment_id, &mut req).await;
+            }
+
+            if let Some(target) = resolve_host(&state, &host).await? {
+                return serve_site(&state, &target, &mut req).await;
+            }
+        }
+
+        self.inner.call(req).await.map(IntoResponse::into_response)
+    }
+}
+
+/// Answers the HTTP-01 challenges of certificates being ordered, for any host
+async fn acme_challenge(state: &State, req: &Request) -> Result<Option<Response>> {
+    if state.config.acme.is_none() {
+        return Ok(None);
+    }
+
+    let Some(token) = req.uri().path().strip_prefix("/.well-known/acme-challenge/") else {
+        return Ok(None);
+    };
+
+    let challenge = AcmeHttpChallenge::get(&state.database, token)
+        .await
+        .map_err(HttpError::from)?;
+
+    Ok(challenge.map(|challenge| {
+        Response::builder()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(challenge.key_authorization)
+    }))
+}
+
+/// The deployment a preview hostname (`d-1234567890.<preview base domain>`) points at
+fn preview_deployment_id(state: &State, host: &str) -> Option<String> {
+    let preview = state.config.preview.as_ref()?;
+    let base_domain = preview.base_domain.trim_matches('.').to_lowercase();
+
+    deployment_id_from_preview_host(host, &base_domain)
+}
+
+/// Resolves a host to its domain entry and the deployment that domain serves
+///
+/// Results, including hosts that aren't a domain of any site, are kept in `Cache.hosts`
+async fn resolve_host(state: &State, host: &str) -> Result<Option<HostTarget>> {
+    if let Some(target) = state.cache.hosts.get(host).await {
+        return Ok(target);
+    }
+
+    let domain = Domain::resolve_by_host(host, state)
+        .await
+        .map_err(HttpError::from)?;
+
+    let target = match domain {
+        Some(domain) => {
+            let deployment_id = match &domain.environment {
+                Some(environment) => SiteEnvironment::get_by_name(&state.database, &domain.site_id, environment)
+                    .await
+                    .map_err(HttpError::from)?
+                    .and_then(|environment| environment.deployment_id),
+                None => Deployment::get_active_by_site_id(&state.database, &domain.site_id)
+                    .await
+                    .map_err(HttpError::from)?
+                    .map(|deployment| deployment.deployment_id),
+            };
+
+            Some(HostTarget { domain, deployment_id })
+        }
+        None => None,
+    };
+
+    state.cache.hosts.insert(host.to_string(), target.clone()).await;
+
+    Ok(target)
+}
+
+/// Loads a deployment together with its site's serving options, access controls and its rules, kept in `Cache.deployments`
+async fn served_deployment(state: &State, deployment_id: &str) -> Result<Option<Arc<ServedDeployment>>> {
+    if let Some(served) = state.cache.deployments.get(deployment_id).await {
+        return Ok(Some(served));
+    }
+
+    let deployment = match Deployment::get_by_id(&state.database, deployment_id).await {
+        Ok(deployment) => deployment,
+        Err(sqlx::Error::RowNotFound) => return Ok(None),
+        Err(error) => return Err(HttpError::from(error).into()),
+    };
+
+    let site = Site::get_by_id(&state.database, &deployment.site_id)
+        .await
+        .map_err(HttpError::from)?;
+
+    let rules = DeploymentRules::get_by_deployment_id(&state.database, deployment_id)
+        .await
+        .map_err(HttpError::from)?;
+
+    let access = SiteAccess::get_by_site_id(&state.database, &site.site_id)
+        .await
+        .map_err(HttpError::from)?;
+
+    let served = Arc::new(ServedDeployment {
+        deployment,
+        options: ServingOptions::from(&site),
+        rules,
+        access,
+    });
+
+    state
+        .cache
+        .deployments
+        .insert(deployment_id.to_string(), served.clone())
+        .await;
+
+    Ok(Some(served))
+}
+
+/// Previews are protected by the site wide access controls
+async fn serve_preview(state: &State, deployment_id: &str, req: &mut Request) -> Result<Response> {
+    let Some(served) = served_deployment(state, deployment_id).await? else {
+        return Ok(default_error_page(state, StatusCode::NOT_FOUND, req.method() == Method::HEAD));
+    };
+
+    if let Some(access) = SiteAccess::for_environment(&served.access, None) {
+        if let Some(response) = access::enforce(state, access, req).await? {
+            return Ok(response);
+        }
+    }
+
+    serve_deployment(state, &served, req).await
+}
+
+async fn serve_site(state: &State, target: &HostTarget, req: &mut Request) -> Result<Response> {
+    let served = match &target.deployment_id {
+        Some(deployment_id) => served_deployment(state, deployment_id).await?,
+        None => None,
+    };
+
+    let Some(served) = served else {
+        info!("No deployment to serve for domain: {:?}", target.domain.domain);
+        return Ok(default_error_page(state, StatusCode::NOT_FOUND, req.method() == Method::HEAD));
+    };
+
+    if let Some(access) = SiteAccess::for_environment(&served.access, target.domain.environment.as_deref()) {
+        if let Some(response) = access::enforce(state, access, req).await? {
+            return Ok(response);
+        }
+    }
+
+    serve_deployment(state, &served, req).await
+}
+
+/// Serves a request from a deployment, failures are answered with the deployment's `500.html`
+async fn serve_deployment(state: &State, served: &ServedDeployment, req: &Request) -> Result<Response> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
+    let deployment_id = &served.deployment.deployment_id;
+
+    match serve_deployment_files(state, served, req).await {
+        Ok(response) => Ok(response),
+        Err(error) => {
+            warn!("Serving deployment {:?} failed: {:?}", deployment_id, error);
+
+            error_page(state, deployment_id, &served.options, StatusCode::INTERNAL_SERVER_ERROR, req)
+                .await
+                .or(Err(error))
+        }
+    }
+}
+
+async fn serve_deployment_files(state: &State, served: &ServedDeployment, req: &Request) -> Result<Response> {
+    let deployment_id = &served.deployment.deployment_id;
+    let options = &served.options;
+    let rules = &served.rules;
+
+    let file = get_file(state, deployment_id, req.uri().path(), options).await?;
+
+    let rule_match = rules.as_ref().and_then(|rules| {
+        rules::resolve(&rules.redirects, req.uri().path(), req.uri().query(), file.is_some())
+    });
+
+    let mut response = match rule_match {
+        Some(RuleMatch::Redirect { location, status }) => Ok(redirect(
+            StatusCode::from_u16(status).unwrap_or(StatusCode::MOVED_PERMANENTLY),
+            location,
+        )),
+        Some(RuleMatch::Rewrite { path, status }) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+
+            match get_file(state, deployment_id, &path, options).await? {
+                Some((file, _)) => serve_file(state, &file, status, req).await,
+                None => not_found(state, deployment_id, options, req).await,
+            }
+        }
+        None => match file {
+            Some((file, kind)) => match resolve::canonical_path(req.uri().path(), kind, options) {
+                Some(location) => {
+                    let location = match req.uri().query() {
+                        Some(query) => format!("{}?{}", location, query),
+                        None => location,
+                    };
+
+                    Ok(redirect(StatusCode::MOVED_PERMANENTLY, location))
+                }
+                None => serve_file(state, &file, StatusCode::OK, req).await,
+            },
+            None => not_found(state, deployment_id, options, req).await,
+        },
+    }?;
+
+    // redirects and not modified answers carry no content the headers could describe
+    if let Some(rules) = rules.as_ref().filter(|_| !response.status().is_redirection()) {
+        // validated when the rules were compiled
+        for (name, value) in rules::resolve_headers(&rules.headers, req.uri().path()) {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+                // the server varies on the encoding already, rules add to that instead of replacing it
+                if name == header::VARY {
+                    response.headers_mut().append(name, value);
+                } else {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+        }
+    }
+
+    Ok(response)
+}
+
+/// Resolves a request path to a file of a deployment, remembered per deployment in `Cache.files`
+async fn get_file(
+    state: &State,
+    deployment_id: &str,
+    request_path: &str,
+    options: &ServingOptions,
+) -> Result<Option<(DeploymentFileEntry, CandidateKind)>> {
+    let key = (deployment_id.to_string(), request_path.to_string());
+
+    if let Some(file) = state.cache.files.get(&key).await {
+        return Ok(file);
+    }
+
+    let file = lookup_file(state, deployment_id, request_path, options).await?;
+
+    state.cache.files.insert(key, file.clone()).await;
+
+    Ok(file)
+}
+
+/// Resolves a request path to a file of a deployment with a single lookup over all candidate paths
+///
+/// Rules files and files whose blob has been deleted are not served
+async fn lookup_file(
+    state: &State,
+    deployment_id: &str,
+    request_path: &str,
+    options: &ServingOptions,
+) -> Result<Option<(DeploymentFileEntry, CandidateKind)>> {
+    let candidates: Vec<(String, CandidateKind)> = resolve::candidates(request_path, options)
+        .into_iter()
+        .filter(|(path, _)| !rules::is_rules_file(path))
+        .collect();
+
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    let paths: Vec<String> = candidates.iter().map(|(path, _)| path.clone()).collect();
+
+    let file = DeploymentFile::resolve_path(&state.database, deployment_id, &paths)
+        .await
+        .map_err(HttpError::from)?;
+
+    match file {
+        Some(file) if file.file_deleted => {
+            warn!("File {:?} was requested but has been deleted", file.file_hash);
+            Ok(None)
+        }
+        Some(file) => {
+            let kind = candidates
+                .into_iter()
+                .find(|(path, _)| *path == file.deployment_file_file_path)
+                .map(|(_, kind)| kind)
+                .unwrap_or(CandidateKind::Exact);
+
+            Ok(Some((file, kind)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Answers a request for a path that doesn't exist
+///
+/// Single page apps get their root index file with status 200, other sites the error page
+async fn not_found(
+    state: &State,
+    deployment_id: &str,
+    options: &ServingOptions,
+    req: &Request,
+) -> Result<Response> {
+    if options.spa_mode {
+        if let Some((file, _)) = get_file(state, deployment_id, "/", options).await? {
+            return serve_file(state, &file, StatusCode::OK, req).await;
+        }
+    }
+
+    error_page(state, deployment_id, options, StatusCode::NOT_FOUND, req).await
+}
+
+/// Serves the deployment's `404.html` / `500.html`, or the configured default page when it has none
+async fn error_page(
+    state: &State,
+    deployment_id: &str,
+    options: &ServingOptions,
+    status: StatusCode,
+    req: &Request,
+) -> Result<Response> {
+    let file_path = format!("/{}.html", status.as_u16());
+
+    if let Some((file, _)) = get_file(state, deployment_id, &file_path, options).await? {
+        return serve_file(state, &file, status, req).await;
+    }
+
+    Ok(default_error_page(state, status, req.method() == Method::HEAD))
+}
+
+/// The configured (or built-in) error page, for hosts without a deployment to serve from
+fn default_error_page(state: &State, status: StatusCode, head_only: bool) -> Response {
+    let page = if status == StatusCode::NOT_FOUND {
+        &state.error_pages.not_found
+    } else {
+        &state.error_pages.internal_error
+    };
+
+    let response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8");
+
+    if head_only {
+        return response.header(header::CONTENT_LENGTH, page.len()).finish();
+    }
+
+    response.body(page.clone())
+}
+
+fn redirect(status: StatusCode, location: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+/// Streams a file from the bucket
+///
+/// Files served with status 200 carry an `ETag` and `Last-Modified` and honor conditional and range requests.
+/// Compressible files are served from a precompressed variant when the client accepts its encoding,
+/// range requests always get the identity representation.
+async fn serve_file(
+    state: &State,
+    file: &DeploymentFileEntry,
+    status: StatusCode,
+    req: &Request,
+) -> Result<Response> {
+    let head_only = req.method() == Method::HEAD;
+    let request_header = |name: HeaderName| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, file.deployment_file_mime_type.as_str());
+
+    let variants = if variants::is_compressible(&file.deployment_file_mime_type) {
+        FileVariant::get_by_file_id(&state.database, file.deployment_file_file_id)
+            .await
+            .map_err(HttpError::from)?
+    } else {
+        Vec::new()
+    };
+
+    let mut variant = None;
+
+    if !variants.is_empty() {
+        response = response.header(header::VARY, "Accept-Encoding");
+
+        if request_header(header::RANGE).is_none() {
+            let available: Vec<&str> = variants.iter().map(|variant| variant.encoding.as_str()).collect();
+            let encoding = encoding::negotiate(request_header(header::ACCEPT_ENCODING), &available);
+
+            variant = encoding.and_then(|encoding| variants.iter().find(|variant| variant.encoding == encoding));
+        }
+    }
+
+    let (object_key, object_size, etag) = match variant {
+        Some(variant) => {
+            response = response.header(header::CONTENT_ENCODING, variant.encoding.as_str());
+
+            (
+                variant.object_key(),
+                Some(variant.variant_size),
+                conditional::etag(&variant.variant_hash),
+            )
+        }
+        None => (file.file_hash.clone(), file.file_size, conditional::etag(&file.file_hash)),
+    };
+
+    let mut range = ByteRange::Full;
+
+    if status == StatusCode::OK {
+        response = response
+            .header(header::ETAG, etag.as_str())
+            .header(header::LAST_MODIFIED, conditional::http_date(file.file_created_at));
+
+        if conditional::is_not_modified(
+            request_header(header::IF_NONE_MATCH),
+            request_header(header::IF_MODIFIED_SINCE),
+            &etag,
+            file.file_created_at,
+        ) {
+            return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+        }
+
+        if let Some(object_size) = object_size {
+            response = response.header(header::ACCEPT_RANGES, "bytes");
+            range = conditional::byte_range(
+                request_header(header::RANGE),
+                request_header(header::IF_RANGE),
+                &etag,
+                file.file_created_at,
+                object_size as u64,
+            );
+        }
+    }
+
+    let total_size = object_size.unwrap_or_default() as u64;
+
+    match range {
+        ByteRange::Unsatisfiable => Ok(response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", total_size))
+            .finish()),
+        ByteRange::Partial { start, end } => {
+            let response = response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, total_size))
+                .header(header::CONTENT_LENGTH, end - start + 1);
+
+            if head_only {
+                return Ok(response.finish());
+            }
+
+            match cached_object(state, &object_key, object_size).await? {
+                Some(content) => Ok(response.body(content.slice(start as usize..=end as usize))),
+                None => Ok(response.body(stream_range(state, &object_key, start, end))),
+            }
+        }
+        ByteRange::Full => {
+            if let Some(object_size) = object_size {
+                response = response.header(header::CONTENT_LENGTH, object_size);
+            }
+
+            if head_only {
+                return Ok(response.body(Body::empty()));
+            }
+
+            if let Some(content) = cached_object(state, &object_key, object_size).await? {
+                return Ok(response.body(content));
+            }
+
+            let stream = state
+                .storage
+                .bucket
+                .get_object_stream(&object_key)
+                .await
+                .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+            let body = Body::from_bytes_stream(stream.bytes.map_err(std::io::Error::other));
+
+            Ok(response.body(body))
+        }
+    }
+}
+
+/// The contents of a small object, kept in `Cache.content` and fetched in full on a miss
+///
+/// Returns `None` for objects too large to be cached, these are streamed from the bucket instead
+async fn cached_object(state: &State, object_key: &str, object_size: Option<i64>) -> Result<Option<Bytes>> {
+    match object_size {
+        Some(size) if size >= 0 && size as u64 <= state.cache.content_max_object_size => {}
+        _ => return Ok(None),
+    }
+
+    if let Some(content) = state.cache.content.get(object_key).await {
+        return Ok(Some(content));
+    }
+
+    let response = state
+        .storage
+        .bucket
+        .get_object(object_key)
+        .await
+        .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+    if response.status_code() != 200 {
+        return Err(HttpError::AnyhowError(eyre!(
+            "Fetching {} failed with status {}",
+            object_key,
+            response.status_code()
+        ))
+        .into());
+    }
+
+    let content = response.bytes().clone();
+
+    state
+        .cache
+        .content
+        .insert(object_key.to_string(), content.clone())
+        .await;
+
+    Ok(Some(content))
+}
+
+/// Streams bytes `start..=end` of a blob with a ranged GET
+///
+/// The download runs in its own task and writes into a bounded channel the response body reads from
+fn stream_range(state: &State, object_key: &str, start: u64, end: u64) -> Body {
+    let (sender, receiver) = mpsc::channel(8);
+    let state = state.clone();
+    let object_key = object_key.to_string();
+
+    async_std::task::spawn(async move {
+        let mut writer = ChannelWriter(sender);
+
+        let result = state
+            .storage
+            .bucket
+            .get_object_range_to_writer(&object_key, start, Some(end), &mut writer)
+            .await;
+
+        let error = match result {
+            Ok(206) | Ok(200) => None,
+            Ok(status) => Some(std::io::Error::other(format!("Ranged GET returned status {}", status))),
+            Err(error) => Some(std::io::Error::other(error)),
+        };
+
+        if let Some(error) = error {
+            warn!("Streaming range of {:?} failed: {:?}", object_key, error);
+            let _ = writer.0.send(Err(error)).await;
+        }
+    });
+
+    Body::from_bytes_stream(receiver)
+}
+
+/// An `AsyncWrite` that forwards every write into a channel
+struct ChannelWriter(mpsc::Sender<std::io::Result<Vec<u8>>>);
+
+impl AsyncWrite for ChannelWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.0.poll_ready(cx) {
+            Poll::Ready(Ok(())) => {
+                self.0
+                    .start_send(Ok(buf.to_vec()))
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+
+                Poll::Ready(Ok(buf.len()))
+            }
+            // the client went away
+            Poll::Ready(Err(_)) => Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.0.close_channel();
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Extracts the hostname (without port or trailing dot, in punycode) the request was made to
+fn request_host(req: &Request) -> Option<String> {
+    let host = req
+        .uri()
+        .host()
+        .or_else(|| req.headers().get(header::HOST).and_then(|x| x.to_str().ok()))?;
+
+    // ip v6 literals are never served
+    if host.starts_with('[') {
+        return None;
+    }
+
+    // lowercased punycode, the form domains are stored in
+    normalize_host(host.split(':').next().unwrap_or_default())
+}
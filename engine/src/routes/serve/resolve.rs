@@ -0,0 +1,178 @@
+use crate::models::site::{Site, TrailingSlash};
+
+/// How request paths are mapped onto deployment files, configured per site
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServingOptions {
+    pub clean_urls: bool,
+    pub trailing_slash: TrailingSlash,
+    pub index_file: String,
+    pub spa_mode: bool,
+}
+
+impl Default for ServingOptions {
+    fn default() -> Self {
+        Self {
+            clean_urls: true,
+            trailing_slash: TrailingSlash::Ignore,
+            index_file: "index.html".to_string(),
+            spa_mode: false,
+        }
+    }
+}
+
+impl From<&Site> for ServingOptions {
+    fn from(site: &Site) -> Self {
+        Self {
+            clean_urls: site.clean_urls,
+            trailing_slash: TrailingSlash::parse(&site.trailing_slash),
+            index_file: site.index_file.clone(),
+            spa_mode: site.spa_mode,
+        }
+    }
+}
+
+/// How a candidate file relates to the requested path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CandidateKind {
+    /// `/about.html` served from `about.html`
+    Exact,
+    /// `/about` served from `about.html`
+    Html,
+    /// `/about` served from `about/index.html`
+    Index,
+}
+
+/// The `file_path`s a request path may be served from, in order of preference
+///
+/// `/about` tries `about`, `about.html` and `about/index.html`, `/about/` tries `about/index.html` and `about.html`.
+pub fn candidates(request_path: &str, options: &ServingOptions) -> Vec<(String, CandidateKind)> {
+    let path = percent_encoding::percent_decode_str(request_path).decode_utf8_lossy();
+    let path = path.trim_start_matches('/');
+    let mut candidates = Vec::with_capacity(3);
+
+    if path.is_empty() || path.ends_with('/') {
+        candidates.push((format!("{}{}", path, options.index_file), CandidateKind::Index));
+
+        let stem = path.trim_end_matches('/');
+        if options.clean_urls && !stem.is_empty() {
+            candidates.push((format!("{}.html", stem), CandidateKind::Html));
+        }
+    } else {
+        candidates.push((path.to_string(), CandidateKind::Exact));
+
+        if options.clean_urls && !path.ends_with(".html") {
+            candidates.push((format!("{}.html", path), CandidateKind::Html));
+        }
+
+        candidates.push((format!("{}/{}", path, options.index_file), CandidateKind::Index));
+    }
+
+    candidates
+}
+
+/// The path to redirect to before serving a file, when the request is not in its canonical form
+///
+/// With clean urls `/about.html` and `/docs/index.html` lose their file name, the trailing slash policy then
+/// decides whether paths served from an `.html` or index file end in a slash.
+pub fn canonical_path(request_path: &str, kind: CandidateKind, options: &ServingOptions) -> Option<String> {
+    // `//host/about` would be a protocol relative location, the file lookup ignores leading slashes all the same
+    // (browsers read a backslash as a slash there too)
+    let request_path = format!("/{}", request_path.trim_start_matches(['/', '\\']));
+    let request_path = request_path.as_str();
+
+    match kind {
+        CandidateKind::Exact if options.clean_urls => {
+            let index_suffix = format!("/{}", options.index_file);
+
+            if let Some(directory) = request_path.strip_suffix(&index_suffix) {
+                return match options.trailing_slash {
+                    TrailingSlash::Never if !directory.is_empty() => Some(directory.to_string()),
+                    _ => Some(format!("{}/", directory)),
+                };
+            }
+
+            let stem = request_path.strip_suffix(".html")?;
+            if stem.is_empty() || stem.ends_with('/') {
+                return None;
+            }
+
+            match options.trailing_slash {
+                TrailingSlash::Always => Some(format!("{}/", stem)),
+                _ => Some(stem.to_string()),
+            }
+        }
+        CandidateKind::Exact => None,
+        CandidateKind::Html | CandidateKind::Index => match options.trailing_slash {
+            TrailingSlash::Always if !request_path.ends_with('/') => Some(format!("{}/", request_path)),
+            TrailingSlash::Never if request_path.len() > 1 && request_path.ends_with('/') => {
+                Some(request_path.trim_end_matches('/').to_string()).filter(|path| !path.is_empty())
+            }
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(request_path: &str, options: &ServingOptions) -> Vec<String> {
+        candidates(request_path, options).into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn test_candidates() {
+        let options = ServingOptions::default();
+
+        assert_eq!(paths("/", &options), vec!["index.html"]);
+        assert_eq!(paths("/about", &options), vec!["about", "about.html", "about/index.html"]);
+        assert_eq!(paths("/about/", &options), vec!["about/index.html", "about.html"]);
+        assert_eq!(paths("/a%20b.png", &options), vec!["a b.png", "a b.png.html", "a b.png/index.html"]);
+
+        let options = ServingOptions {
+            clean_urls: false,
+            index_file: "default.htm".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(paths("/docs", &options), vec!["docs", "docs/default.htm"]);
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        let mut options = ServingOptions::default();
+
+        assert_eq!(canonical_path("/about.html", CandidateKind::Exact, &options), Some("/about".to_string()));
+        assert_eq!(canonical_path("/index.html", CandidateKind::Exact, &options), Some("/".to_string()));
+        assert_eq!(canonical_path("/docs/index.html", CandidateKind::Exact, &options), Some("/docs/".to_string()));
+        assert_eq!(canonical_path("/about", CandidateKind::Html, &options), None);
+        assert_eq!(canonical_path("/about/", CandidateKind::Index, &options), None);
+
+        options.trailing_slash = TrailingSlash::Always;
+        assert_eq!(canonical_path("/about", CandidateKind::Index, &options), Some("/about/".to_string()));
+        assert_eq!(canonical_path("/about.html", CandidateKind::Exact, &options), Some("/about/".to_string()));
+        assert_eq!(canonical_path("/app.js", CandidateKind::Exact, &options), None);
+
+        options.trailing_slash = TrailingSlash::Never;
+        assert_eq!(canonical_path("/about/", CandidateKind::Html, &options), Some("/about".to_string()));
+        assert_eq!(canonical_path("/docs/index.html", CandidateKind::Exact, &options), Some("/docs".to_string()));
+        assert_eq!(canonical_path("/", CandidateKind::Index, &options), None);
+    }
+
+    #[test]
+    fn test_canonical_path_collapses_leading_slashes() {
+        let mut options = ServingOptions {
+            trailing_slash: TrailingSlash::Always,
+            ..Default::default()
+        };
+
+        assert_eq!(canonical_path("//about", CandidateKind::Html, &options), Some("/about/".to_string()));
+        assert_eq!(canonical_path("/\\about", CandidateKind::Html, &options), Some("/about/".to_string()));
+        assert_eq!(canonical_path("//docs/index.html", CandidateKind::Exact, &options), Some("/docs/".to_string()));
+        assert_eq!(canonical_path("///evil.com/about.html", CandidateKind::Exact, &options), Some("/evil.com/about/".to_string()));
+
+        options.trailing_slash = TrailingSlash::Never;
+        assert_eq!(canonical_path("//about/", CandidateKind::Html, &options), Some("/about".to_string()));
+        assert_eq!(canonical_path("//", CandidateKind::Index, &options), None);
+    }
+}
@@ -0,0 +1,282 @@
+use futures::TryStreamExt;
+use poem::{web::Data, Body, Result};
+use poem_openapi::{
+    param::Path,
+    payload::{Binary, Json},
+    ApiResponse, Object, OpenApi,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    assets::{AssetFile, SpooledFile},
+    middlewares::auth::UserAuth,
+    models::{
+        deployment::{
+            events::DeploymentStatus,
+            manifest::{DeploymentManifest, ManifestEntry},
+            promotion::DeploymentPromotion,
+            rules::DeploymentRules,
+            Deployment,
+        },
+        environment::SiteEnvironment,
+        site::SiteId,
+    },
+    routes::{error::HttpError, ApiTags},
+    state::State,
+};
+
+use super::queue_preview;
+
+pub struct SiteDeploymentManifestApi;
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct ManifestRequest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct ManifestResponse {
+    /// The sha256 hashes that still need to be uploaded
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct FinalizeRequest {
+    /// Make the deployment live once finalized (defaults to false)
+    pub promote: Option<bool>,
+}
+
+#[derive(ApiResponse)]
+pub enum FinalizeResponse {
+    #[oai(status = 200)]
+    Ok(Json<Deployment>),
+
+    /// Not all blobs referenced by the manifest have been uploaded
+    #[oai(status = 409)]
+    Missing(Json<ManifestResponse>),
+}
+
+#[OpenApi]
+impl SiteDeploymentManifestApi {
+    /// Submit a deployment manifest
+    ///
+    /// Announces the files of a deployment by their sha256, the response lists the hashes
+    /// the server does not have yet. Upload only those, then finalize the deployment.
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/manifest",
+        method = "post",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn submit_manifest(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+        payload: Json<ManifestRequest>,
+    ) -> Result<Json<ManifestResponse>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let deployment = get_site_deployment(&state, &site_id.0, &deployment_id.0).await?;
+
+        let entries = payload
+            .0
+            .files
+            .into_iter()
+            .map(ManifestEntry::validate)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(HttpError::BadRequest)?;
+
+        info!(
+            "Received manifest with {} files for deployment: {:?}",
+            entries.len(),
+            deployment.deployment_id
+        );
+
+        DeploymentManifest::add_entries(&state.database, &deployment.deployment_id, &entries)
+            .await
+            .map_err(HttpError::from)?;
+
+        Deployment::set_status(&state, &deployment.deployment_id, DeploymentStatus::Uploading, None)
+            .await
+            .map_err(HttpError::from)?;
+
+        let missing = DeploymentManifest::get_missing(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        Ok(Json(ManifestResponse { missing }))
+    }
+
+    /// Upload a blob referenced by the manifest
+    ///
+    /// The request body is the raw file contents, its sha256 must match the path
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/blobs/:file_hash",
+        method = "put",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn upload_blob(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+        file_hash: Path<String>,
+        body: Binary<Body>,
+    ) -> Result<Json<ManifestResponse>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let deployment = get_site_deployment(&state, &site_id.0, &deployment_id.0).await?;
+        let file_hash = file_hash.0.to_lowercase();
+
+        if !DeploymentManifest::references_hash(&state.database, &deployment.deployment_id, &file_hash)
+            .await
+            .map_err(HttpError::from)?
+        {
+            Err(HttpError::BadRequest(format!(
+                "Blob {} is not part of the manifest",
+                file_hash
+            )))?;
+        }
+
+        let reader = Box::pin(body.0.into_bytes_stream()).into_async_read();
+
+        let mut spooled = SpooledFile::spool(reader)
+            .await
+            .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+        if spooled.file_hash != file_hash {
+            Err(HttpError::BadRequest(format!(
+                "Blob hash mismatch, expected {} got {}",
+                file_hash, spooled.file_hash
+            )))?;
+        }
+
+        AssetFile::store_spooled(&state, &mut spooled)
+            .await
+            .map_err(HttpError::from)?;
+
+        let missing = DeploymentManifest::get_missing(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        Ok(Json(ManifestResponse { missing }))
+    }
+
+    /// Finalize a manifest deployment
+    ///
+    /// Links all manifest files to the deployment once every blob is present
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/finalize",
+        method = "post",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn finalize_deployment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+        payload: Json<FinalizeRequest>,
+    ) -> Result<FinalizeResponse> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let deployment = get_site_deployment(&state, &site_id.0, &deployment_id.0).await?;
+
+        let missing = DeploymentManifest::get_missing(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        if !missing.is_empty() {
+            return Ok(FinalizeResponse::Missing(Json(ManifestResponse { missing })));
+        }
+
+        let promote = payload.promote.unwrap_or(false);
+
+        if promote {
+            let files = DeploymentManifest::count(&state.database, &deployment.deployment_id)
+                .await
+                .map_err(HttpError::from)?;
+
+            // would replace the live site with nothing
+            if files == 0 {
+                Err(HttpError::BadRequest("An empty manifest can't be promoted".to_string()))?;
+            }
+        }
+
+        Deployment::set_status(&state, &deployment.deployment_id, DeploymentStatus::Processing, None)
+            .await
+            .map_err(HttpError::from)?;
+
+        if let Err(error) = DeploymentManifest::finalize(&state.database, &deployment.deployment_id).await {
+            Deployment::set_status(
+                &state,
+                &deployment.deployment_id,
+                DeploymentStatus::Failed,
+                Some(error.to_string()),
+            )
+            .await
+            .map_err(HttpError::from)?;
+
+            Err(HttpError::from(error))?;
+        }
+
+        if let Err(error) = DeploymentRules::compile(&state, &deployment.deployment_id).await {
+            Deployment::set_status(
+                &state,
+                &deployment.deployment_id,
+                DeploymentStatus::Failed,
+                Some(error.to_string()),
+            )
+            .await
+            .map_err(HttpError::from)?;
+
+            Err(error)?;
+        }
+
+        state.cache.invalidate_deployment(&deployment.deployment_id).await;
+
+        // queued before the deployment turns ready so event subscribers know to wait for it
+        queue_preview(&state, &site_id.0, &deployment.deployment_id).await?;
+
+        Deployment::set_status(&state, &deployment.deployment_id, DeploymentStatus::Ready, None)
+            .await
+            .map_err(HttpError::from)?;
+
+        // only ready deployments can be made live
+        if promote {
+            DeploymentPromotion::promote(&state.database, &site_id.0, &deployment.deployment_id)
+                .await
+                .map_err(HttpError::from)?;
+        }
+
+        let deployment = Deployment::get_by_id(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        SiteEnvironment::track_deployment(&state.database, &deployment)
+            .await
+            .map_err(HttpError::from)?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(FinalizeResponse::Ok(Json(deployment)))
+    }
+}
+
+/// Loads a deployment, making sure it belongs to the site it was requested through
+async fn get_site_deployment(
+    state: &State,
+    site_id: &str,
+    deployment_id: &str,
+) -> Result<Deployment, HttpError> {
+    let deployment = Deployment::get_by_id(&state.database, deployment_id).await?;
+
+    if deployment.site_id != site_id {
+        return Err(HttpError::Forbidden);
+    }
+
+    Ok(deployment)
+}
@@ -1,19 +1,43 @@
-use poem::{web::Data, Result};
-use poem_openapi::{param::Path, payload::Json, OpenApi};
+use std::time::{Duration, Instant};
+
+use futures::{stream::BoxStream, StreamExt};
+use poem::{web::{sse::Event, Data}, Result};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::{EventStream, Json},
+    types::multipart::Upload,
+    Object, OpenApi,
+};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
     handlers::car::CarRequest, middlewares::auth::UserAuth, models::{
-        deployment::{preview::DeploymentPreview, Deployment, DeploymentFile, DeploymentFileEntry},
+        deployment::{
+            archive::{normalize_entry_path, ArchiveFormat, InvalidEntryPath},
+            events::{DeploymentEvent, DeploymentProgress, DeploymentStatus, DeploymentSubscription},
+            metadata::DeploymentMetadata,
+            preview::{preview_hostname, DeploymentPreview}, promotion::DeploymentPromotion,
+            rules::DeploymentRules, Deployment,
+            DeploymentFile, DeploymentFileEntry,
+        },
         domain::Domain,
-        site::{Site, SiteId},
+        environment::SiteEnvironment,
+        site::{DeploymentFilter, Site, SiteId},
     }, routes::{error::HttpError, ApiTags}, state::State
 };
 
 use super::UploadPayload;
 
+pub mod manifest;
+
 pub struct SiteDeploymentsApi;
 
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct DeploymentPinRequest {
+    pub pinned: bool,
+}
+
 #[OpenApi]
 impl SiteDeploymentsApi {
     /// Get all deployments
@@ -27,10 +51,30 @@ impl SiteDeploymentsApi {
         user: UserAuth,
         state: Data<&State>,
         #[oai(name = "site_id", style = "simple")] site_id: Path<String>,
+        /// Only deployments of this branch
+        branch: Query<Option<String>>,
+        /// Only deployments of this (abbreviated) commit
+        commit_sha: Query<Option<String>>,
+        /// Only deployments with this environment label
+        environment: Query<Option<String>>,
     ) -> Result<Json<Vec<Deployment>>> {
         user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        Site::get_deployments(&state.database, &site_id.0)
+        if commit_sha
+            .0
+            .as_ref()
+            .is_some_and(|sha| !sha.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            Err(HttpError::BadRequest("commit_sha must be hexadecimal".to_string()))?;
+        }
+
+        let filter = DeploymentFilter {
+            branch: branch.0,
+            commit_sha: commit_sha.0,
+            environment: environment.0,
+        };
+
+        Site::get_deployments(&state.database, &site_id.0, &filter)
             .await
             .map_err(HttpError::from)
             .map(Json)
@@ -59,6 +103,178 @@ impl SiteDeploymentsApi {
             .map_err(poem::Error::from)
     }
 
+    /// Delete a deployment
+    ///
+    /// Removes the deployment with its previews, car archive and IPFS pin, and right away the files no other deployment uses.
+    /// The live deployment, deployments an environment serves and pinned deployments are only removed when `force` is set,
+    /// removing the live deployment leaves the site without a live deployment.
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id",
+        method = "delete",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn delete_deployment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+        force: Query<Option<bool>>,
+    ) -> Result<Json<Deployment>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        info!(
+            "Deleting deployment: {:?} for site: {:?} for user: {:?}",
+            deployment_id.0, site_id.0, user
+        );
+
+        let deployment = Deployment::get_by_id(&state.database, &deployment_id.0)
+            .await
+            .map_err(HttpError::from)?;
+
+        if deployment.site_id != site_id.0 {
+            Err(HttpError::Forbidden)?;
+        }
+
+        if !force.0.unwrap_or(false) {
+            let site = Site::get_by_id(&state.database, &site_id.0)
+                .await
+                .map_err(HttpError::from)?;
+
+            if site.active_deployment_id.as_deref() == Some(deployment.deployment_id.as_str()) {
+                Err(HttpError::Conflict(
+                    "This deployment is live, promote another deployment or set force to delete it".to_string(),
+                ))?;
+            }
+
+            if deployment.pinned {
+                Err(HttpError::Conflict(
+                    "This deployment is pinned, unpin it or set force to delete it".to_string(),
+                ))?;
+            }
+
+            let environments = SiteEnvironment::get_by_deployment_id(&state.database, &deployment.deployment_id)
+                .await
+                .map_err(HttpError::from)?;
+
+            if !environments.is_empty() {
+                let names: Vec<&str> = environments.iter().map(|environment| environment.name.as_str()).collect();
+
+                Err(HttpError::Conflict(format!(
+                    "This deployment is served by the {} environment, point it at another deployment or set force to delete it",
+                    names.join(", ")
+                )))?;
+            }
+        }
+
+        Deployment::delete(&state, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        Ok(Json(deployment))
+    }
+
+    /// Pin or unpin a deployment
+    ///
+    /// Pinned deployments are never removed by retention policies
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/pin",
+        method = "put",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn pin_deployment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+        payload: Json<DeploymentPinRequest>,
+    ) -> Result<Json<Deployment>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let deployment = Deployment::get_by_id(&state.database, &deployment_id.0)
+            .await
+            .map_err(HttpError::from)?;
+
+        if deployment.site_id != site_id.0 {
+            Err(HttpError::Forbidden)?;
+        }
+
+        Deployment::set_pinned(&state.database, &deployment.deployment_id, payload.pinned)
+            .await
+            .map_err(HttpError::from)
+            .map(Json)
+            .map_err(poem::Error::from)
+    }
+
+    /// Stream deployment events
+    ///
+    /// Server-sent events for status transitions, file progress, IPFS pinning and preview generation.
+    /// The first event is always the current status, followed by the IPFS and preview status when known.
+    /// The stream ends once the deployment is `ready` or `failed` and its IPFS pin and preview are no longer pending.
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/events",
+        method = "get",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn get_deployment_events(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+    ) -> Result<EventStream<BoxStream<'static, DeploymentEvent>>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let deployment = Deployment::get_by_id(&state.database, &deployment_id.0)
+            .await
+            .map_err(HttpError::from)?;
+
+        if deployment.site_id != site_id.0 {
+            Err(HttpError::Forbidden)?;
+        }
+
+        // subscribe before loading the snapshot so no transition is missed in between
+        let subscription = state.events.subscribe(&deployment.deployment_id);
+
+        let deployment = Deployment::get_by_id(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        let mut progress = DeploymentProgress::default();
+        let snapshot = progress.sync(&deployment);
+
+        let watch = EventWatch {
+            state: state.0.clone(),
+            deployment_id: deployment.deployment_id,
+            subscription,
+            progress,
+            settle_deadline: None,
+        };
+
+        let updates = futures::stream::unfold(Some(watch), |watch| async move {
+            let mut watch = watch?;
+
+            if watch.is_done() {
+                return None;
+            }
+
+            let events = watch.next_events().await?;
+
+            Some((futures::stream::iter(events), Some(watch)))
+        })
+        .flatten();
+
+        let stream = futures::stream::iter(snapshot).chain(updates).boxed();
+
+        Ok(EventStream::new(stream)
+            .keep_alive(Duration::from_secs(15))
+            .to_event(|event| {
+                Event::message(serde_json::to_string(&event).unwrap_or_default())
+                    .event_type(event.event_type())
+            }))
+    }
+
     /// Get a deployment preview by id
     #[oai(
         path = "/site/:site_id/deployment/:deployment_id/preview",
@@ -105,42 +321,120 @@ impl SiteDeploymentsApi {
 
         info!("Uploading file: {:?}", payload.data);
 
-        let deployment = Deployment::new(&state.database, site_id, payload.context)
+        // deployments created without files are filled later (see the manifest flow), they can't be made live yet
+        let has_files = payload.data.is_some() || !payload.files.is_empty();
+
+        if !has_files && payload.promote.unwrap_or(false) {
+            Err(HttpError::Conflict(
+                "Deployments without files can't be promoted, promote them once they are ready".to_string(),
+            ))?;
+        }
+
+        let metadata = payload_metadata(payload.metadata.map(|x| x.0), payload.context.as_deref())?
+            .unwrap_or_default();
+
+        let deployment = Deployment::new(&state.database, site_id, payload.context, metadata)
             .await
             .map_err(HttpError::from)?;
 
         info!("Deployment complete");
 
-        if let Some(data) = payload.data {
-            let data = data.into_vec().await.unwrap();
+        if has_files {
+            process_upload(
+                &state,
+                &deployment,
+                payload.data,
+                payload.files,
+                payload.promote.unwrap_or(true),
+            )
+            .await?;
+        }
 
-            if let Some(car_bucket) = &state.storage.car_bucket {
-                let path = format!("{}/car.zip", deployment.deployment_id);
-                let _ = car_bucket.put_object(&path, &data).await.unwrap();
-                
-                info!("Car uploaded to: {:?}", path);
+        let deployment = Deployment::get_by_id(&state.database, &deployment.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
 
-                if let Some(rabbit) = &state.rabbit {
-                    if let Some(car) = &rabbit.car {
-                        car.queue_car(CarRequest {
-                            deployment_id: deployment.deployment_id.clone(),
-                            file_path: path.clone(),
-                        }).await;
-                    }
-                }
-            }
+        Ok(Json(deployment))
+    }
 
-            Deployment::upload_files(&deployment, &state, data)
-                .await
-                .unwrap();
-        }
+    /// Promote a deployment
+    ///
+    /// Makes the deployment the live deployment of the site
+    #[oai(
+        path = "/site/:site_id/deployment/:deployment_id/promote",
+        method = "post",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn promote_deployment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        deployment_id: Path<String>,
+    ) -> Result<Json<Site>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        // let cutoff_date = Utc::now() - Duration::days(365);
-        // Deployment::cleanup_old_files(&state, cutoff_date)
-        //     .await
-        //     .unwrap();
+        info!(
+            "Promoting deployment: {:?} for site: {:?} for user: {:?}",
+            deployment_id.0, site_id.0, user
+        );
 
-        Ok(Json(deployment))
+        let site = DeploymentPromotion::promote(&state.database, &site_id.0, &deployment_id.0)
+            .await
+            .map_err(HttpError::from)?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(Json(site))
+    }
+
+    /// Rollback the live deployment
+    ///
+    /// Makes the previously promoted deployment live again
+    #[oai(
+        path = "/site/:site_id/deployments/rollback",
+        method = "post",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn rollback_deployment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+    ) -> Result<Json<Site>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        info!("Rolling back site: {:?} for user: {:?}", site_id.0, user);
+
+        let site = DeploymentPromotion::rollback(&state.database, &site_id.0)
+            .await
+            .map_err(HttpError::from)?
+            .ok_or(HttpError::NotFound)?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(Json(site))
+    }
+
+    /// Get the promotion history
+    #[oai(
+        path = "/site/:site_id/deployments/promotions",
+        method = "get",
+        tag = "ApiTags::Deployment"
+    )]
+    pub async fn get_deployment_promotions(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+    ) -> Result<Json<Vec<DeploymentPromotion>>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        DeploymentPromotion::get_by_site_id(&state.database, &site_id.0)
+            .await
+            .map_err(HttpError::from)
+            .map(Json)
+            .map_err(poem::Error::from)
     }
 
     /// Upload files to a deployment
@@ -172,59 +466,38 @@ impl SiteDeploymentsApi {
             .await
             .map_err(HttpError::from)?;
 
-        info!("Deployment complete");
-
-        if let Some(data) = payload.data {
-            let data = data.into_vec().await.unwrap();
-
-            if let Some(car_bucket) = &state.storage.car_bucket {
-                let path = format!("{}/car.zip", deployment.deployment_id);
-                let _ = car_bucket.put_object(&path, &data).await.unwrap();
-
-                if let Some(rabbit) = &state.rabbit {
-                    if let Some(car) = &rabbit.car {
-                        car.queue_car(CarRequest {
-                            deployment_id: deployment_id.clone(),
-                            file_path: path.clone(),
-                        }).await;
-                    }
-                }
+        if deployment.site_id != site_id.0 {
+            Err(HttpError::Forbidden)?;
+        }
 
-                info!("Car uploaded to: {:?}", path);
-            }
+        info!("Deployment complete");
 
-            Deployment::upload_files(&deployment, &state, data)
+        // metadata goes first so the environment of the deployment is known once its files are in
+        if let Some(metadata) = payload_metadata(payload.metadata.map(|x| x.0), payload.context.as_deref())? {
+            Deployment::update_metadata(&state.database, &deployment_id, &metadata)
                 .await
-                .unwrap();
+                .map_err(HttpError::from)?;
         }
 
+        process_upload(
+            &state,
+            &deployment,
+            payload.data,
+            payload.files,
+            payload.promote.unwrap_or(false),
+        )
+        .await?;
+
         // update context on deployment
         if let Some(context) = payload.context {
             Deployment::update_context(&state.database, &deployment_id, &context)
                 .await
-                .unwrap();
+                .map_err(HttpError::from)?;
         }
 
-        // TODO: improve trigger based on routing implementation
-        // get first domain if exists for site
-        let domain = Domain::get_by_site_id(&site_id.0, &state)
+        let deployment = Deployment::get_by_id(&state.database, &deployment_id)
             .await
-            .ok()
-            .and_then(|domains| domains.first().cloned());
-
-        if let Some(domain) = domain {
-            info!("A domain was found for this site");
-            if let Some(rabbit) = &state.rabbit {
-                info!("Queueing bunshot for domain: {:?}", domain);
-                let domain = domain.domain();
-                if let Some(previews) = &rabbit.previews {
-                    previews.queue_bunshot(&site_id.0, &deployment_id, &domain)
-                        .await;
-                }
-            }
-        } else {
-            info!("No domain was found for this site");
-        }
+            .map_err(HttpError::from)?;
 
         Ok(Json(deployment))
     }
@@ -274,27 +547,239 @@ impl SiteDeploymentsApi {
     ) -> Result<Json<serde_json::Value>> {
         user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        if let Some(rabbit) = &state.rabbit {
-            info!("Queueing bunshot for deployment: {:?}", deployment_id.0);
+        queue_preview(&state, &site_id.0, &deployment_id.0).await?;
 
-            let domain = Domain::get_by_site_id(&site_id.0, &state)
-                .await
-                .ok()
-                .and_then(|domains| domains.first().cloned());
-
-            if let Some(domain) = domain {
-                info!("Queueing bunshot for domain: {:?}", domain.domain());
-                if let Some(previews) = &rabbit.previews {
-                    previews.queue_bunshot(&site_id.0, &deployment_id.0, &domain.domain())
-                        .await;
+        Ok(Json(serde_json::Value::Null))
+    }
+}
+
+/// How often an event stream checks the database for sub-states reported by other services, like the preview worker
+const EVENTS_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+/// How long an event stream waits for a pending IPFS pin or preview once the deployment is ready or failed
+const EVENTS_SETTLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Follows a deployment for its event stream until it and its IPFS pin and preview have settled
+struct EventWatch {
+    state: State,
+    deployment_id: String,
+    subscription: DeploymentSubscription,
+    progress: DeploymentProgress,
+    settle_deadline: Option<Instant>,
+}
+
+impl EventWatch {
+    fn is_done(&mut self) -> bool {
+        if self.progress.is_settled() {
+            return true;
+        }
+
+        if !self.progress.is_terminal() {
+            return false;
+        }
+
+        let deadline = *self
+            .settle_deadline
+            .get_or_insert_with(|| Instant::now() + EVENTS_SETTLE_TIMEOUT);
+
+        Instant::now() >= deadline
+    }
+
+    /// The next published event, or what changed in the database since, `None` once the deployment is gone
+    async fn next_events(&mut self) -> Option<Vec<DeploymentEvent>> {
+        match async_std::future::timeout(EVENTS_SYNC_INTERVAL, self.subscription.recv()).await {
+            Ok(Some(event)) => {
+                let news = self.progress.apply(&event);
+
+                return Some(if news { vec![event] } else { vec![] });
+            }
+            Ok(None) => async_std::task::sleep(EVENTS_SYNC_INTERVAL).await,
+            Err(_) => {}
+        }
+
+        let deployment = Deployment::get_by_id(&self.state.database, &self.deployment_id)
+            .await
+            .ok()?;
+
+        Some(self.progress.sync(&deployment))
+    }
+}
+
+/// Queues a screenshot of the deployment
+///
+/// Targets the preview hostname of the deployment, without a preview base domain configured
+/// the first domain of the site is used instead (which shows whatever is live)
+async fn queue_preview(state: &State, site_id: &str, deployment_id: &str) -> Result<(), HttpError> {
+    let Some(previews) = state.rabbit.as_ref().and_then(|rabbit| rabbit.previews.as_ref()) else {
+        info!("No rabbit was found");
+        return Ok(());
+    };
+
+    let target = match &state.config.preview {
+        Some(preview) => Some(format!(
+            "{}://{}",
+            preview.scheme,
+            preview_hostname(deployment_id, &preview.base_domain.trim_matches('.').to_lowercase())
+        )),
+        None => Domain::get_by_site_id(site_id, state)
+            .await
+            .ok()
+            .and_then(|domains| domains.first().map(|domain| domain.domain())),
+    };
+
+    let Some(target) = target else {
+        info!("No domain was found for this site");
+        return Ok(());
+    };
+
+    info!("Queueing bunshot for deployment: {:?} at: {:?}", deployment_id, target);
+
+    previews.queue_bunshot(site_id, deployment_id, &target).await;
+
+    Deployment::set_preview_status(state, deployment_id, "queued").await?;
+
+    Ok(())
+}
+
+/// Validates the metadata of an upload, falling back to what can be derived from a github action context
+///
+/// Only metadata the client sent is refused when invalid, derived metadata is sanitized so legacy uploads keep working
+fn payload_metadata(
+    metadata: Option<DeploymentMetadata>,
+    context: Option<&str>,
+) -> Result<Option<DeploymentMetadata>, HttpError> {
+    match metadata {
+        Some(metadata) => metadata.validate().map(Some).map_err(HttpError::BadRequest),
+        None => Ok(context
+            .and_then(DeploymentMetadata::from_context)
+            .map(DeploymentMetadata::sanitize)),
+    }
+}
+
+/// Runs an upload through the deployment lifecycle
+///
+/// The deployment ends up `ready` (and optionally live) with its preview queued, or `failed` with the reason and without files when ingestion errors.
+/// Once ready, the environment named after its label or branch is moved to it.
+async fn process_upload(
+    state: &State,
+    deployment: &Deployment,
+    data: Option<Upload>,
+    files: Vec<Upload>,
+    promote: bool,
+) -> Result<(), HttpError> {
+    let deployment_id = &deployment.deployment_id;
+
+    Deployment::set_status(state, deployment_id, DeploymentStatus::Uploading, None).await?;
+
+    if let Err(error) = upload_payload_files(state, deployment, data, files).await {
+        // streamed archives are refused midway, the entries linked before shouldn't be served
+        DeploymentFile::delete_by_deployment_id(&state.database, deployment_id).await?;
+        state.cache.invalidate_deployment(deployment_id).await;
+
+        Deployment::set_status(state, deployment_id, DeploymentStatus::Failed, Some(error.to_string())).await?;
+
+        return Err(error);
+    }
+
+    Deployment::set_status(state, deployment_id, DeploymentStatus::Processing, None).await?;
+
+    // queued before the deployment turns ready so event subscribers know to wait for it
+    queue_preview(state, &deployment.site_id, deployment_id).await?;
+
+    Deployment::set_status(state, deployment_id, DeploymentStatus::Ready, None).await?;
+
+    // only ready deployments can be made live
+    if promote {
+        DeploymentPromotion::promote(&state.database, &deployment.site_id, deployment_id).await?;
+    }
+
+    let deployment = Deployment::get_by_id(&state.database, deployment_id).await?;
+    SiteEnvironment::track_deployment(&state.database, &deployment).await?;
+
+    state.cache.invalidate_hosts();
+
+    Ok(())
+}
+
+/// Ingests the archive and/or individual files of an upload into a deployment
+///
+/// Zip archives are additionally handed to the car pipeline for IPFS pinning
+async fn upload_payload_files(
+    state: &State,
+    deployment: &Deployment,
+    data: Option<Upload>,
+    files: Vec<Upload>,
+) -> Result<(), HttpError> {
+    if let Some(data) = data {
+        // the upload has already been spooled to a temporary file by poem, keep it on disk
+        let data = data.into_file().into_std().await;
+
+        let head = data
+            .try_clone()
+            .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+        let format = ArchiveFormat::sniff(&mut async_std::fs::File::from(head))
+            .await
+            .map_err(|error| HttpError::AnyhowError(error.into()))?
+            .ok_or_else(|| {
+                HttpError::BadRequest("Unsupported archive format, expected zip, tar, tar.gz or tar.zst".to_string())
+            })?;
+
+        if format == ArchiveFormat::Zip {
+            if let Some(car_bucket) = &state.storage.car_bucket {
+                let path = format!("{}/car.zip", deployment.deployment_id);
+                let car_file = data
+                    .try_clone()
+                    .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+                car_bucket
+                    .put_object_stream(&mut async_std::fs::File::from(car_file), &path)
+                    .await
+                    .map_err(|error| HttpError::AnyhowError(error.into()))?;
+
+                info!("Car uploaded to: {:?}", path);
+
+                if let Some(rabbit) = &state.rabbit {
+                    if let Some(car) = &rabbit.car {
+                        car.queue_car(CarRequest {
+                            deployment_id: deployment.deployment_id.clone(),
+                            file_path: path.clone(),
+                        }).await;
+
+                        Deployment::set_ipfs_status(state, &deployment.deployment_id, "pending", None).await?;
+                    }
                 }
-            } else {
-                info!("No domain was found for this site");
             }
-        } else {
-            info!("No rabbit was found");
         }
 
-        Ok(Json(serde_json::Value::Null))
+        Deployment::upload_files(deployment, state, data, format)
+            .await
+            .map_err(|error| match error.downcast::<InvalidEntryPath>() {
+                Ok(error) => HttpError::BadRequest(error.to_string()),
+                Err(error) => HttpError::from(error),
+            })?;
     }
+
+    if !files.is_empty() {
+        let mut named_files = Vec::with_capacity(files.len());
+
+        for file in files {
+            let path = file
+                .file_name()
+                .and_then(normalize_entry_path)
+                .ok_or_else(|| HttpError::BadRequest("Every uploaded file needs a relative path as its file name".to_string()))?;
+
+            named_files.push((path, file.into_file().into_std().await));
+        }
+
+        Deployment::upload_multipart_files(deployment, state, named_files)
+            .await
+            .map_err(HttpError::from)?;
+    }
+
+    DeploymentRules::compile(state, &deployment.deployment_id).await?;
+
+    // files may have been added to a deployment that is already being served
+    state.cache.invalidate_deployment(&deployment.deployment_id).await;
+
+    Ok(())
 }
@@ -1,12 +1,17 @@
 use poem::{web::Data, Result};
-use poem_openapi::{param::Path, payload::Json, ApiResponse, Object, OpenApi};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::Json,
+    ApiResponse, Object, OpenApi,
+};
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::{
     middlewares::auth::UserAuth,
     models::{
-        domain::{Domain, DomainPending, DomainSubmission},
+        domain::{normalize_domain, Domain, DomainPending, DomainSubmission},
+        environment::SiteEnvironment,
         site::SiteId,
     },
     routes::{error::HttpError, ApiTags},
@@ -36,10 +41,24 @@ struct DomainPreflightResponseConflicts {
     conflicts: Vec<DomainSubmission>,
 }
 
+#[derive(Deserialize, Object)]
+pub struct DomainEnvironmentRequest {
+    /// The environment to serve, omit to serve the live deployment
+    environment: Option<String>,
+}
+
+/// An existing domain the preflighted name overlaps with, other sites' details are left out
+#[derive(Deserialize, Object)]
+struct DomainOverlap {
+    domain: String,
+    /// The domain belongs to the site the preflight was made for
+    own_site: bool,
+}
+
 #[derive(Deserialize, Object)]
 struct DomainPreflightResponseData {
-    overrides: Vec<Domain>,
-    invalidates: Vec<Domain>,
+    overrides: Vec<DomainOverlap>,
+    invalidates: Vec<DomainOverlap>,
 }
 
 #[OpenApi]
@@ -76,15 +95,8 @@ impl SiteDomainsApi {
     ) -> Result<Json<DomainSubmission>> {
         user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        // validate domain is atleast 3 characters and has a dot seperator, no spaces, trim, etc
-        // use regex to validate
-        let domain_regex = regex::Regex::new(r"^(\*\.)?([a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?\.)+[a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?$").unwrap();
-        if !domain_regex.is_match(&payload.domain) {
-            // invalid domain
-            Err(HttpError::NotFound)?;
-        }
-
-        let corrected_domain = payload.domain.trim();
+        let corrected_domain = normalize_domain(&payload.domain)
+            .map_err(|error| HttpError::BadRequest(error.to_string()))?;
 
         let domain = Domain::create_for_site(&site_id, &corrected_domain, &state)
             .await
@@ -110,12 +122,14 @@ impl SiteDomainsApi {
     ) -> Result<Json<serde_json::Value>> {
         user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        let existing_domain = Domain::get_by_site_id_and_domain(&site_id.0, &domain.0, &state)
+        let domain = path_domain(&domain.0);
+
+        let existing_domain = Domain::get_by_site_id_and_domain(&site_id.0, &domain, &state)
             .await
             .map_err(HttpError::from)?;
 
         if let Some(_existing_domain) = existing_domain {
-            Domain::delete_by_site_id_and_domain(&site_id.0, &domain.0, &state)
+            Domain::delete_by_site_id_and_domain(&site_id.0, &domain, &state)
                 .await
                 .map_err(HttpError::from)?;
 
@@ -125,12 +139,12 @@ impl SiteDomainsApi {
         }
 
         let existing_domain_pending =
-            DomainPending::get_by_site_id_and_domain(&site_id.0, &domain.0, &state)
+            DomainPending::get_by_site_id_and_domain(&site_id.0, &domain, &state)
                 .await
                 .map_err(HttpError::from)?;
 
         if let Some(_existing_domain_pending) = existing_domain_pending {
-            DomainPending::delete_by_site_id_and_domain(&site_id.0, &domain.0, &state)
+            DomainPending::delete_by_site_id_and_domain(&site_id.0, &domain, &state)
                 .await
                 .map_err(HttpError::from)?;
 
@@ -142,12 +156,76 @@ impl SiteDomainsApi {
         Err(HttpError::NotFound.into())
     }
 
+    /// Verify a pending site domain
+    ///
+    /// Looks up the `_edgeserver-challenge` TXT record of the domain, the domain is added to the site once it holds the challenge.
+    /// Returns the pending domain with the reason the check failed otherwise
+    #[oai(
+        path = "/site/:site_id/domains/:domain/verify",
+        method = "post",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn verify_site_domain(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        domain: Path<String>,
+    ) -> Result<Json<DomainSubmission>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let pending = DomainPending::get_by_site_id_and_domain(&site_id.0, &path_domain(&domain.0), &state)
+            .await
+            .map_err(HttpError::from)?
+            .ok_or(HttpError::NotFound)?;
+
+        pending
+            .do_challenge(&state)
+            .await
+            .map_err(HttpError::from)
+            .map(Json)
+            .map_err(poem::Error::from)
+    }
+
+    /// Bind a site domain to an environment
+    #[oai(
+        path = "/site/:site_id/domains/:domain/environment",
+        method = "put",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn set_site_domain_environment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        domain: Path<String>,
+        payload: Json<DomainEnvironmentRequest>,
+    ) -> Result<Json<Domain>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        if let Some(environment) = &payload.environment {
+            SiteEnvironment::get_by_name(&state.database, &site_id.0, environment)
+                .await
+                .map_err(HttpError::from)?
+                .ok_or(HttpError::NotFound)?;
+        }
+
+        Domain::set_environment(&site_id.0, &path_domain(&domain.0), payload.environment.as_deref(), &state)
+            .await
+            .map_err(HttpError::from)?
+            .ok_or(HttpError::NotFound)
+            .map(Json)
+            .map_err(poem::Error::from)
+    }
+
     /// Preflight check a site domain
     ///
-    /// Checks wether or not the domain will require validation
-    /// It does so by checking overlap
+    /// Reports the existing domains adding this domain would affect, on this or other sites.
+    /// `invalidates` are taken over once the domain is verified (the same name, or every domain below a wildcard),
+    /// `overrides` are wildcards above it that keep existing but no longer serve this name.
+    /// Adding the domain requires verification when either is non-empty
     #[oai(
-        path = "/site/:site_id/domains/:domain/preflight",
+        path = "/site/:site_id/domains/preflight",
         method = "get",
         tag = "ApiTags::Site"
     )]
@@ -156,23 +234,20 @@ impl SiteDomainsApi {
         user: UserAuth,
         state: Data<&State>,
         site_id: Path<String>,
-        domain: Path<String>,
+        domain: Query<String>,
     ) -> Result<DomainPreflightResponse> {
         user.verify_access_to(&SiteId(&site_id.0)).await?;
 
-        // validate domain is atleast 3 characters and has a dot seperator, no spaces, trim, etc
-        // use regex to validate
-        let domain_regex = regex::Regex::new(r"^(\*\.)?([a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?\.)+[a-zA-Z0-9]([a-zA-Z0-9-]*[a-zA-Z0-9])?$").unwrap();
-        if !domain_regex.is_match(&domain) {
-            // invalid domain
-            return Ok(DomainPreflightResponse::MalformattedInput(Json(
-                MalformattedInputResponse {
-                    message: "Invalid domain".to_string(),
-                },
-            )));
-        }
-
-        let domain = domain.trim();
+        let domain = match normalize_domain(&domain.0) {
+            Ok(domain) => domain,
+            Err(error) => {
+                return Ok(DomainPreflightResponse::MalformattedInput(Json(
+                    MalformattedInputResponse {
+                        message: error.to_string(),
+                    },
+                )))
+            }
+        };
 
         let invalidates = Domain::get_soft_overlap(&domain, &state)
             .await
@@ -182,11 +257,26 @@ impl SiteDomainsApi {
             .await
             .map_err(HttpError::from)?;
 
+        let overlaps = |domains: Vec<Domain>| {
+            domains
+                .into_iter()
+                .map(|domain| DomainOverlap {
+                    own_site: domain.site_id == site_id.0,
+                    domain: domain.domain,
+                })
+                .collect()
+        };
+
         Ok(DomainPreflightResponse::Result(Json(
             DomainPreflightResponseData {
-                overrides,
-                invalidates,
+                overrides: overlaps(overrides),
+                invalidates: overlaps(invalidates),
             },
         )))
     }
 }
+
+/// The stored form of a domain named in the path, names that don't pass `normalize_domain` (added before it existed) are only lowercased
+fn path_domain(domain: &str) -> String {
+    normalize_domain(domain).unwrap_or_else(|_| domain.trim().trim_end_matches('.').to_lowercase())
+}
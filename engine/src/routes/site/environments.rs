@@ -0,0 +1,125 @@
+use poem::{web::Data, Result};
+use poem_openapi::{param::Path, payload::Json, Object, OpenApi};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    middlewares::auth::UserAuth,
+    models::{
+        deployment::{metadata::is_label, Deployment},
+        environment::SiteEnvironment,
+        site::SiteId,
+    },
+    routes::{error::HttpError, ApiTags},
+    state::State,
+};
+
+pub struct SiteEnvironmentsApi;
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct SetEnvironmentRequest {
+    pub deployment_id: String,
+}
+
+#[OpenApi]
+impl SiteEnvironmentsApi {
+    /// Get all site environments
+    #[oai(
+        path = "/site/:site_id/environments",
+        method = "get",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn get_site_environments(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+    ) -> Result<Json<Vec<SiteEnvironment>>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        SiteEnvironment::get_by_site_id(&state.database, &site_id.0)
+            .await
+            .map_err(HttpError::from)
+            .map(Json)
+            .map_err(poem::Error::from)
+    }
+
+    /// Point an environment at a deployment
+    ///
+    /// Creates the environment when it does not exist yet.
+    /// Environments are also created and moved along automatically when a deployment with a branch or environment label is uploaded.
+    #[oai(
+        path = "/site/:site_id/environments/:name",
+        method = "put",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn set_site_environment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        name: Path<String>,
+        payload: Json<SetEnvironmentRequest>,
+    ) -> Result<Json<SiteEnvironment>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        if !is_label(&name.0) {
+            Err(HttpError::BadRequest(
+                "Environment names consist of lowercase letters, digits and dashes".to_string(),
+            ))?;
+        }
+
+        let deployment = Deployment::get_by_id(&state.database, &payload.deployment_id)
+            .await
+            .map_err(HttpError::from)?;
+
+        if deployment.site_id != site_id.0 {
+            Err(HttpError::Forbidden)?;
+        }
+
+        info!(
+            "Pointing environment: {:?} of site: {:?} at deployment: {:?}",
+            name.0, site_id.0, deployment.deployment_id
+        );
+
+        let environment =
+            SiteEnvironment::set_deployment(&state.database, &site_id.0, &name.0, &deployment.deployment_id, None)
+                .await
+                .map_err(HttpError::from)?;
+
+        state.cache.invalidate_hosts();
+
+        Ok(Json(environment))
+    }
+
+    /// Delete a site environment
+    ///
+    /// Domains bound to the environment serve the live deployment again
+    #[oai(
+        path = "/site/:site_id/environments/:name",
+        method = "delete",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn delete_site_environment(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        name: Path<String>,
+    ) -> Result<Json<serde_json::Value>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        if !SiteEnvironment::delete(&state.database, &site_id.0, &name.0)
+            .await
+            .map_err(HttpError::from)?
+        {
+            Err(HttpError::NotFound)?;
+        }
+
+        state.cache.invalidate_hosts();
+
+        Ok(Json(serde_json::json!({
+            "message": "Environment deleted"
+        })))
+    }
+}
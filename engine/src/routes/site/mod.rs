@@ -1,6 +1,10 @@
 use poem::{web::Data, Result};
+use ipnetwork::IpNetwork;
 use poem_openapi::{
-    param::Path, payload::Json, types::multipart::Upload, Multipart, Object, OpenApi,
+    param::{Path, Query},
+    payload::Json,
+    types::multipart::{JsonField, Upload},
+    Multipart, Object, OpenApi,
 };
 use serde::{Deserialize, Serialize};
 use tracing::info;
@@ -8,17 +12,21 @@ use tracing::info;
 use crate::{
     middlewares::auth::UserAuth,
     models::{
-        site::{Site, SiteId},
+        access::{SiteAccess, SiteAccessInfo},
+        deployment::metadata::{is_label, DeploymentMetadata},
+        site::{Site, SiteId, TrailingSlash},
         team::Team,
     },
     routes::ApiTags,
     state::State,
+    utils::hash::hash_access_password,
 };
 
 use super::error::HttpError;
 
 pub mod deployments;
 pub mod domains;
+pub mod environments;
 pub mod keys;
 
 #[derive(Debug, Deserialize, Serialize, Object)]
@@ -38,8 +46,57 @@ struct File {
 
 #[derive(Debug, Multipart)]
 pub struct UploadPayload {
+    /// A zip, tar, tar.gz or tar.zst archive of the site
     data: Option<Upload>,
+    /// Individual files, each named after its path relative to the site root
+    files: Vec<Upload>,
+    /// Free-form context, displayed by the dashboard
     context: Option<String>,
+    /// Commit and CI information, derived from `context` when it was sent by the github action
+    metadata: Option<JsonField<DeploymentMetadata>>,
+    /// Make the deployment live once its files are uploaded (defaults to true when files are included)
+    promote: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct SiteRetentionRequest {
+    /// Always keep the newest N deployments
+    #[oai(validator(minimum(value = "1")))]
+    pub keep_last: Option<i32>,
+    /// Always keep deployments younger than N days
+    #[oai(validator(minimum(value = "1")))]
+    pub days: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct SiteServingRequest {
+    /// Serve `/about` from `about.html` and redirect `/about.html` to `/about`
+    pub clean_urls: bool,
+    pub trailing_slash: TrailingSlash,
+    /// The file served for directory paths, defaults to `index.html`
+    pub index_file: Option<String>,
+    /// Serve the root index file with status 200 instead of the 404 page, for single page apps
+    #[oai(default)]
+    pub spa_mode: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct SiteAccessRequest {
+    /// Only protect the domains bound to this environment, omit to protect every domain and preview of the site
+    pub environment: Option<String>,
+    /// Ask for these credentials with http basic auth
+    pub basic_auth: Option<BasicAuthCredentials>,
+    /// Ask for this password on a password page, visitors that enter it get a signed cookie
+    pub password: Option<String>,
+    /// Only serve requests from these addresses or ranges, e.g. `203.0.113.7` or `10.0.0.0/8`
+    #[oai(default)]
+    pub allowed_ips: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Object)]
+pub struct BasicAuthCredentials {
+    pub username: String,
+    pub password: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Object)]
@@ -53,7 +110,9 @@ pub fn api_routes() -> impl OpenApi {
     (
         SiteApi,
         deployments::SiteDeploymentsApi,
+        deployments::manifest::SiteDeploymentManifestApi,
         domains::SiteDomainsApi,
+        environments::SiteEnvironmentsApi,
         keys::SiteKeysApi,
     )
 }
@@ -138,6 +197,229 @@ impl SiteApi {
         todo!();
     }
 
+    /// Update the retention policy
+    ///
+    /// Deployments that are neither among the newest `keep_last` nor younger than `days` are removed periodically.
+    /// The live deployment and pinned deployments are always kept, omit both fields to keep everything.
+    #[oai(
+        path = "/site/:site_id/retention",
+        method = "put",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn update_site_retention(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        payload: Json<SiteRetentionRequest>,
+    ) -> Result<Json<Site>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        info!("Updating retention for site: {:?} for user: {:?}", site_id.0, user);
+
+        Site::update_retention(&state.database, &site_id.0, payload.keep_last, payload.days)
+            .await
+            .map_err(HttpError::from)
+            .map(Json)
+            .map_err(poem::Error::from)
+    }
+
+    /// Update how paths are resolved
+    ///
+    /// `/about` is served from `about`, `about.html` (with clean urls) or `about/<index file>`, in that order.
+    /// The trailing slash policy redirects paths served from an `.html` or index file to their canonical form.
+    /// Paths that don't exist get the deployment's `404.html`, or the root index file in spa mode.
+    #[oai(
+        path = "/site/:site_id/serving",
+        method = "put",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn update_site_serving(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        payload: Json<SiteServingRequest>,
+    ) -> Result<Json<Site>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        let index_file = payload.index_file.as_deref().unwrap_or("index.html");
+
+        if index_file.is_empty()
+            || index_file.len() > 255
+            || index_file.contains(['/', '\\'])
+            || index_file.starts_with('.')
+        {
+            return Err(HttpError::BadRequest(format!("Invalid index file: {:?}", index_file)).into());
+        }
+
+        info!("Updating serving options for site: {:?} for user: {:?}", site_id.0, user);
+
+        let site = Site::update_serving(
+            &state.database,
+            &site_id.0,
+            payload.clean_urls,
+            payload.trailing_slash,
+            index_file,
+            payload.spa_mode,
+        )
+        .await
+        .map_err(HttpError::from)?;
+
+        state.cache.invalidate_site(&site_id.0);
+
+        Ok(Json(site))
+    }
+
+    /// Get the access controls
+    ///
+    /// Lists the site wide access controls and those of single environments, credentials are never returned
+    #[oai(
+        path = "/site/:site_id/access",
+        method = "get",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn get_site_access(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+    ) -> Result<Json<Vec<SiteAccessInfo>>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        SiteAccess::get_by_site_id(&state.database, &site_id.0)
+            .await
+            .map_err(HttpError::from)
+            .map(|access| Json(access.into_iter().map(SiteAccessInfo::from).collect()))
+            .map_err(poem::Error::from)
+    }
+
+    /// Protect a site
+    ///
+    /// Replaces the access controls of the site, or of a single environment when `environment` is set.
+    /// Requests have to come from one of `allowed_ips` (when set) and pass basic auth or the password page (when set).
+    /// Previews are protected by the site wide access controls. Every change signs out visitors of the password page.
+    #[oai(
+        path = "/site/:site_id/access",
+        method = "put",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn update_site_access(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        payload: Json<SiteAccessRequest>,
+    ) -> Result<Json<SiteAccessInfo>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        if let Some(environment) = &payload.environment {
+            if !is_label(environment) {
+                Err(HttpError::BadRequest(
+                    "Environment names consist of lowercase letters, digits and dashes".to_string(),
+                ))?;
+            }
+        }
+
+        if payload.basic_auth.is_none() && payload.password.is_none() && payload.allowed_ips.is_empty() {
+            Err(HttpError::BadRequest(
+                "Set basic_auth, password or allowed_ips, delete the access controls to make the site public".to_string(),
+            ))?;
+        }
+
+        if let Some(credentials) = &payload.basic_auth {
+            if credentials.username.is_empty() || credentials.username.contains(':') {
+                Err(HttpError::BadRequest("Invalid basic auth username".to_string()))?;
+            }
+
+            if credentials.password.is_empty() {
+                Err(HttpError::BadRequest("The basic auth password can't be empty".to_string()))?;
+            }
+        }
+
+        if payload.password.as_deref() == Some("") {
+            Err(HttpError::BadRequest("The password can't be empty".to_string()))?;
+        }
+
+        let allowed_ips = payload
+            .allowed_ips
+            .iter()
+            .map(|ip| {
+                ip.trim()
+                    .parse::<IpNetwork>()
+                    // store the range, `10.1.2.3/8` becomes `10.0.0.0/8`
+                    .and_then(|network| IpNetwork::new(network.network(), network.prefix()))
+                    .map_err(|_| HttpError::BadRequest(format!("Invalid address or range: {:?}", ip)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let hash = |password: &str| {
+            hash_access_password(password)
+                .map_err(|error| HttpError::AnyhowError(color_eyre::eyre::eyre!("Hashing failed: {}", error)))
+        };
+
+        let basic_auth_hash = payload
+            .basic_auth
+            .as_ref()
+            .map(|credentials| hash(&credentials.password))
+            .transpose()?;
+        let password_hash = payload.password.as_deref().map(hash).transpose()?;
+
+        let basic_auth = payload
+            .basic_auth
+            .as_ref()
+            .zip(basic_auth_hash.as_deref())
+            .map(|(credentials, hash)| (credentials.username.as_str(), hash));
+
+        info!("Updating access controls for site: {:?} for user: {:?}", site_id.0, user);
+
+        let access = SiteAccess::set(
+            &state.database,
+            &site_id.0,
+            payload.environment.as_deref(),
+            basic_auth,
+            password_hash.as_deref(),
+            &allowed_ips,
+        )
+        .await
+        .map_err(HttpError::from)?;
+
+        state.cache.invalidate_site(&site_id.0);
+
+        Ok(Json(access.into()))
+    }
+
+    /// Make a site public
+    ///
+    /// Removes the site wide access controls, or those of a single environment when `environment` is set
+    #[oai(
+        path = "/site/:site_id/access",
+        method = "delete",
+        tag = "ApiTags::Site"
+    )]
+    pub async fn delete_site_access(
+        &self,
+        user: UserAuth,
+        state: Data<&State>,
+        site_id: Path<String>,
+        environment: Query<Option<String>>,
+    ) -> Result<Json<serde_json::Value>> {
+        user.verify_access_to(&SiteId(&site_id.0)).await?;
+
+        if !SiteAccess::delete(&state.database, &site_id.0, environment.0.as_deref())
+            .await
+            .map_err(HttpError::from)?
+        {
+            Err(HttpError::NotFound)?;
+        }
+
+        state.cache.invalidate_site(&site_id.0);
+
+        Ok(Json(serde_json::json!({
+            "message": "Access controls deleted"
+        })))
+    }
+
     /// Delete a site
     #[oai(path = "/site/:site_id", method = "delete", tag = "ApiTags::Site")]
     pub async fn delete_site(
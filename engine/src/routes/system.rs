@@ -1,6 +1,10 @@
+use crate::gc::{self, GcReport};
+use crate::middlewares::auth::UserAuth;
+use crate::routes::error::HttpError;
 use crate::state::State;
 use crate::utils::build_info::{BuildInformation, build_build_information};
 use poem::web::Data;
+use poem::Result;
 use poem_openapi::{payload::Json, ApiResponse, Object, OpenApi};
 use crate::routes::ApiTags;
 
@@ -25,6 +29,14 @@ pub enum BuildInfoResponse {
     Ok(Json<BuildInformation>),
 }
 
+#[derive(ApiResponse)]
+pub enum GcReportResponse {
+    #[oai(status = 200)]
+    Ok(Json<GcReport>),
+    #[oai(status = 100)]
+    FeatureDisabled(Json<String>),
+}
+
 #[OpenApi]
 impl SystemApi {
     #[oai(path = "/system/ipfs", method = "get", tag = "ApiTags::System")]
@@ -41,5 +53,24 @@ impl SystemApi {
     async fn build(&self, _state: Data<&State>) -> BuildInfoResponse {
         BuildInfoResponse::Ok(Json(build_build_information()))
     }
-}
 
+    /// Garbage collection dry run
+    ///
+    /// Lists the unreferenced blobs the next garbage collection would delete, admin only
+    #[oai(path = "/system/gc", method = "get", tag = "ApiTags::System")]
+    async fn gc_report(&self, user: UserAuth, state: Data<&State>) -> Result<GcReportResponse> {
+        user.required_admin().await?;
+
+        let Some(config) = &state.config.gc else {
+            return Ok(GcReportResponse::FeatureDisabled(Json(
+                "Garbage collection is not enabled".to_string(),
+            )));
+        };
+
+        let report = gc::collect(&state, config, true)
+            .await
+            .map_err(HttpError::from)?;
+
+        Ok(GcReportResponse::Ok(Json(report)))
+    }
+}
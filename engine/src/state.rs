@@ -4,7 +4,7 @@ use color_eyre::eyre::Result;
 use figment::{Figment, providers::Env};
 use serde::Deserialize;
 
-use crate::{cache::Cache, database::Database, handlers::TaskRabbit, ipfs::IPFSModule, storage::Storage};
+use crate::{cache::Cache, database::Database, dns::DnsResolver, handlers::TaskRabbit, ipfs::IPFSModule, models::deployment::events::DeploymentEvents, storage::Storage, tls::CertificateResolver};
 
 pub type State = Arc<AppState>;
 
@@ -16,6 +16,11 @@ pub struct AppState {
     pub cache: Cache,
     pub rabbit: Option<TaskRabbit>,
     pub ipfs: Option<IPFSModule>,
+    pub events: DeploymentEvents,
+    pub error_pages: ErrorPages,
+    pub dns: DnsResolver,
+    /// Certificates served on the TLS listener, kept up to date by the ACME worker
+    pub tls: Arc<CertificateResolver>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -28,6 +33,14 @@ pub struct AppConfig {
     pub github_app: Option<GithubAppConfig>,
     pub amqp: Option<AMQPConfig>,
     pub ipfs: Option<IPFSConfig>,
+    pub gc: Option<GcConfig>,
+    pub preview: Option<PreviewConfig>,
+    pub error_pages: Option<ErrorPagesConfig>,
+    pub cache: Option<CacheConfig>,
+    pub access: Option<AccessConfig>,
+    pub dns: Option<DnsConfig>,
+    pub domains: Option<DomainsConfig>,
+    pub acme: Option<AcmeConfig>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -87,6 +100,281 @@ pub struct IPFSConfig {
     pub public_cluster_url: String,
 }
 
+/// Every deployment is served at `d-<id>.<base_domain>`, point a wildcard dns record at the engine
+#[derive(Deserialize, Debug)]
+pub struct PreviewConfig {
+    pub base_domain: String,
+    /// Scheme used when sending preview urls to the screenshot service
+    #[serde(default = "PreviewConfig::default_scheme")]
+    pub scheme: String,
+}
+
+impl PreviewConfig {
+    fn default_scheme() -> String {
+        "https".to_string()
+    }
+}
+
+/// Html files served when a deployment has no `404.html` / `500.html` of its own
+#[derive(Deserialize, Debug)]
+pub struct ErrorPagesConfig {
+    pub not_found: Option<String>,
+    pub internal_error: Option<String>,
+}
+
+/// The default error pages of deployed sites, read once at startup
+#[derive(Debug)]
+pub struct ErrorPages {
+    pub not_found: String,
+    pub internal_error: String,
+}
+
+impl ErrorPages {
+    fn load(config: Option<&ErrorPagesConfig>) -> Result<Self> {
+        let read = |path: Option<&String>, default: &str| -> Result<String> {
+            match path {
+                Some(path) => Ok(std::fs::read_to_string(path)?),
+                None => Ok(default.to_string()),
+            }
+        };
+
+        Ok(Self {
+            not_found: read(
+                config.and_then(|config| config.not_found.as_ref()),
+                include_str!("routes/404.html"),
+            )?,
+            internal_error: read(
+                config.and_then(|config| config.internal_error.as_ref()),
+                include_str!("routes/500.html"),
+            )?,
+        })
+    }
+}
+
+/// Sizes of the serving caches, see `Cache`
+#[derive(Deserialize, Debug)]
+pub struct CacheConfig {
+    /// Total size of the cached file contents
+    #[serde(default = "CacheConfig::default_content_max_bytes")]
+    pub content_max_bytes: u64,
+    /// Files larger than this are never held in memory
+    #[serde(default = "CacheConfig::default_content_max_object_size")]
+    pub content_max_object_size: u64,
+    /// How long host and path lookups are remembered, changes made through another engine show up after this
+    #[serde(default = "CacheConfig::default_resolution_ttl_seconds")]
+    pub resolution_ttl_seconds: u64,
+}
+
+impl CacheConfig {
+    pub fn default_content_max_bytes() -> u64 {
+        256 * 1024 * 1024
+    }
+
+    pub fn default_content_max_object_size() -> u64 {
+        1024 * 1024
+    }
+
+    pub fn default_resolution_ttl_seconds() -> u64 {
+        60
+    }
+}
+
+/// How the access controls of sites identify clients
+#[derive(Deserialize, Debug)]
+pub struct AccessConfig {
+    /// Take the client address from `X-Real-IP` / `Forwarded` and the scheme from `X-Forwarded-Proto`,
+    /// only enable this behind a proxy that sets them, clients could otherwise pick their own address
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
+}
+
+/// Nameservers domain challenges are looked up with, the system resolver is used when unset
+#[derive(Deserialize, Debug)]
+pub struct DnsConfig {
+    /// Comma separated, `1.1.1.1,8.8.8.8` or `127.0.0.1:5353`
+    pub nameservers: Option<String>,
+}
+
+/// Background verification of pending domains and re-verification of active ones, see `verifier`
+#[derive(Deserialize, Debug)]
+pub struct DomainsConfig {
+    /// How often the verifier looks for work
+    #[serde(default = "DomainsConfig::default_poll_interval_seconds")]
+    pub poll_interval_seconds: u64,
+    /// Delay before the second challenge check, doubled after every failed check
+    #[serde(default = "DomainsConfig::default_retry_base_seconds")]
+    pub retry_base_seconds: u64,
+    #[serde(default = "DomainsConfig::default_retry_max_seconds")]
+    pub retry_max_seconds: u64,
+    /// Pending domains older than this are removed
+    #[serde(default = "DomainsConfig::default_pending_expiry_days")]
+    pub pending_expiry_days: i64,
+    /// How often active domains are checked to still point at the engine
+    #[serde(default = "DomainsConfig::default_recheck_interval_hours")]
+    pub recheck_interval_hours: i64,
+    /// Consecutive failed checks after which a domain is detached from its site and has to be verified again, 0 only flags it
+    ///
+    /// Defaults to 3 when a CNAME or address target is configured, domains are only flagged otherwise
+    /// since customers often remove the TXT record once the domain is verified
+    pub detach_after_failures: Option<i32>,
+    /// The name customers point their domains at with a CNAME record
+    pub cname_target: Option<String>,
+    /// Comma separated addresses customers point their domains at with A / AAAA records
+    pub addresses: Option<String>,
+}
+
+impl DomainsConfig {
+    pub fn default_poll_interval_seconds() -> u64 {
+        60
+    }
+
+    pub fn default_retry_base_seconds() -> u64 {
+        60
+    }
+
+    pub fn default_retry_max_seconds() -> u64 {
+        6 * 60 * 60
+    }
+
+    pub fn default_pending_expiry_days() -> i64 {
+        7
+    }
+
+    pub fn default_recheck_interval_hours() -> i64 {
+        24
+    }
+
+    pub fn default_detach_after_failures() -> i32 {
+        3
+    }
+
+    /// The configured `detach_after_failures`, or its default depending on whether targets are configured
+    pub fn detach_after_failures(&self) -> i32 {
+        let has_targets = [&self.cname_target, &self.addresses]
+            .into_iter()
+            .any(|target| target.as_deref().is_some_and(|target| !target.trim().is_empty()));
+
+        match self.detach_after_failures {
+            Some(detach_after_failures) => detach_after_failures,
+            None if has_targets => Self::default_detach_after_failures(),
+            None => 0,
+        }
+    }
+}
+
+impl Default for DomainsConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_seconds: Self::default_poll_interval_seconds(),
+            retry_base_seconds: Self::default_retry_base_seconds(),
+            retry_max_seconds: Self::default_retry_max_seconds(),
+            pending_expiry_days: Self::default_pending_expiry_days(),
+            recheck_interval_hours: Self::default_recheck_interval_hours(),
+            detach_after_failures: None,
+            cname_target: None,
+            addresses: None,
+        }
+    }
+}
+
+/// Certificates for verified domains, enabled by setting `ACME_ENCRYPTION_KEY`
+#[derive(Deserialize, Debug)]
+pub struct AcmeConfig {
+    /// Hex encoded 32 byte key the account and certificate keys are encrypted with in the database
+    pub encryption_key: String,
+    #[serde(default = "AcmeConfig::default_directory_url")]
+    pub directory_url: String,
+    /// Email address the ACME server sends expiry notices to
+    pub contact: Option<String>,
+    /// PEM file of an extra root to trust for the directory, for a local Pebble instance
+    pub ca_certificate: Option<String>,
+    /// Address the TLS listener binds to
+    #[serde(default = "AcmeConfig::default_tls_bind")]
+    pub tls_bind: String,
+    /// Receives `POST <url>/present` and `POST <url>/cleanup` with `{"fqdn", "value"}` to manage DNS-01 TXT records
+    pub dns_webhook_url: Option<String>,
+    /// Use DNS-01 for every domain instead of only for wildcards
+    #[serde(default)]
+    pub prefer_dns_01: bool,
+    /// Wait after presenting a TXT record before asking for validation
+    #[serde(default = "AcmeConfig::default_dns_propagation_seconds")]
+    pub dns_propagation_seconds: u64,
+    #[serde(default = "AcmeConfig::default_renew_before_days")]
+    pub renew_before_days: i64,
+    /// Wait after a failed issuance before trying the domain again
+    #[serde(default = "AcmeConfig::default_retry_minutes")]
+    pub retry_minutes: i64,
+    #[serde(default = "AcmeConfig::default_interval_minutes")]
+    pub interval_minutes: u64,
+}
+
+impl AcmeConfig {
+    fn default_directory_url() -> String {
+        "https://acme-v02.api.letsencrypt.org/directory".to_string()
+    }
+
+    fn default_tls_bind() -> String {
+        "0.0.0.0:443".to_string()
+    }
+
+    fn default_dns_propagation_seconds() -> u64 {
+        30
+    }
+
+    fn default_renew_before_days() -> i64 {
+        30
+    }
+
+    fn default_retry_minutes() -> i64 {
+        60
+    }
+
+    fn default_interval_minutes() -> u64 {
+        10
+    }
+
+    pub fn encryption_key(&self) -> Result<[u8; 32]> {
+        hex::decode(self.encryption_key.trim())
+            .ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| color_eyre::eyre::eyre!("ACME_ENCRYPTION_KEY must be 32 bytes, hex encoded"))
+    }
+
+    /// The client the directory is talked to with, trusting `ca_certificate` when set
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+
+        if let Some(path) = &self.ca_certificate {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&std::fs::read(path)?)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Garbage collection of unreferenced blobs, enabled by setting any `GC_` variable
+#[derive(Deserialize, Debug)]
+pub struct GcConfig {
+    #[serde(default = "GcConfig::default_interval_hours")]
+    pub interval_hours: u64,
+    /// Blobs younger than this are never collected
+    #[serde(default = "GcConfig::default_grace_period_hours")]
+    pub grace_period_hours: i64,
+    /// Only log what would be collected
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl GcConfig {
+    fn default_interval_hours() -> u64 {
+        24
+    }
+
+    fn default_grace_period_hours() -> i64 {
+        24
+    }
+}
+
 impl AppState {
     pub async fn new() -> Result<Self> {
         // let config = Config::builder()
@@ -110,14 +398,34 @@ impl AppState {
                 .map(|key| format!("amqp.{}", key.as_str().to_lowercase()).into()))
             .merge(Env::prefixed("IPFS_")
                 .map(|key| format!("ipfs.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("PREVIEW_")
+                .map(|key| format!("preview.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("GC_")
+                .map(|key| format!("gc.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("ERROR_PAGES_")
+                .map(|key| format!("error_pages.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("CACHE_")
+                .map(|key| format!("cache.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("ACCESS_")
+                .map(|key| format!("access.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("DNS_")
+                .map(|key| format!("dns.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("DOMAINS_")
+                .map(|key| format!("domains.{}", key.as_str().to_lowercase()).into()))
+            .merge(Env::prefixed("ACME_")
+                .map(|key| format!("acme.{}", key.as_str().to_lowercase()).into()))
             .extract::<AppConfig>()
             .expect("Failed to load AppConfig configuration");
 
         let database = Database::new(&config.database_url).await?;
 
+        let error_pages = ErrorPages::load(config.error_pages.as_ref())?;
+
         let storage = Storage::from_config(&config);
 
-        let cache = Cache::default();
+        let cache = Cache::new(config.cache.as_ref());
+
+        let dns = DnsResolver::from_config(config.dns.as_ref())?;
 
         let rabbit = if let Some(amqp) = &config.amqp {
             Some(TaskRabbit::init(amqp).await)
@@ -138,6 +446,10 @@ impl AppState {
             cache,
             rabbit,
             ipfs,
+            events: DeploymentEvents::default(),
+            error_pages,
+            dns,
+            tls: Arc::new(CertificateResolver::default()),
         })
     }
 }
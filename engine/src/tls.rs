@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, Result as IoResult},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use async_std::channel::{bounded, Receiver};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use poem::{
+    http::uri::Scheme,
+    listener::{Acceptor, Listener},
+    web::{LocalAddr, RemoteAddr},
+};
+use rustls::{
+    crypto::aws_lc_rs::sign::any_supported_type,
+    pki_types::CertificateDer,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use tokio_rustls::server::TlsStream;
+use tracing::debug;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Connections that don't finish the handshake within this are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Picks the certificate for a connection by its SNI name, the exact name first and then the wildcard one level up
+#[derive(Debug, Default)]
+pub struct CertificateResolver {
+    certificates: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertificateResolver {
+    /// Swaps in the full set of certificates, by domain
+    pub fn replace(&self, certificates: HashMap<String, Arc<CertifiedKey>>) {
+        *self.certificates.write().unwrap() = certificates;
+    }
+
+    pub fn insert(&self, domain: &str, certificate: Arc<CertifiedKey>) {
+        self.certificates
+            .write()
+            .unwrap()
+            .insert(domain.to_string(), certificate);
+    }
+
+    fn lookup(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        let server_name = server_name.trim_end_matches('.').to_lowercase();
+        let certificates = self.certificates.read().unwrap();
+
+        certificates.get(&server_name).cloned().or_else(|| {
+            let (_, parent) = server_name.split_once('.')?;
+
+            certificates.get(&format!("*.{}", parent)).cloned()
+        })
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello.server_name().and_then(|name| self.lookup(name))
+    }
+}
+
+/// Builds the key rustls serves from a PEM chain and PEM private key
+pub fn certified_key(certificate_pem: &str, key_pem: &[u8]) -> Result<CertifiedKey> {
+    let chain = rustls_pemfile::certs(&mut certificate_pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+
+    if chain.is_empty() {
+        return Err(eyre!("Certificate chain is empty"));
+    }
+
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])?.ok_or_else(|| eyre!("No private key found"))?;
+
+    Ok(CertifiedKey::new(chain, any_supported_type(&key)?))
+}
+
+/// When the leaf certificate of a PEM chain expires
+pub fn expires_at(certificate_pem: &str) -> Result<DateTime<Utc>> {
+    let leaf: CertificateDer = rustls_pemfile::certs(&mut certificate_pem.as_bytes())
+        .next()
+        .ok_or_else(|| eyre!("Certificate chain is empty"))??;
+
+    let (_, certificate) = X509Certificate::from_der(leaf.as_ref())?;
+
+    DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| eyre!("Certificate expiry is out of range"))
+}
+
+/// Terminates TLS on top of another listener, with the certificates of `CertificateResolver`
+pub struct TlsListener<T> {
+    inner: T,
+    resolver: Arc<CertificateResolver>,
+}
+
+impl<T> TlsListener<T> {
+    pub fn new(inner: T, resolver: Arc<CertificateResolver>) -> Self {
+        Self { inner, resolver }
+    }
+}
+
+type Accepted<Io> = IoResult<(TlsStream<Io>, LocalAddr, RemoteAddr)>;
+
+impl<T> Listener for TlsListener<T>
+where
+    T: Listener,
+    T::Acceptor: 'static,
+{
+    type Acceptor = TlsAcceptor<T::Acceptor>;
+
+    async fn into_acceptor(self) -> IoResult<Self::Acceptor> {
+        let mut inner = self.inner.into_acceptor().await?;
+        let local_addr = inner.local_addr();
+
+        // the process wide provider, reqwest pulls in ring next to aws-lc-rs so it can't be inferred
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        let mut config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(self.resolver);
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let tls = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let (sender, receiver) = bounded::<Accepted<<T::Acceptor as Acceptor>::Io>>(128);
+
+        // handshakes run on their own tasks so a slow client doesn't hold up the others
+        async_std::task::spawn(async move {
+            while !sender.is_closed() {
+                let (stream, local_addr, remote_addr, _) = match inner.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        let _ = sender.send(Err(error)).await;
+                        continue;
+                    }
+                };
+
+                let tls = tls.clone();
+                let sender = sender.clone();
+
+                async_std::task::spawn(async move {
+                    match async_std::future::timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(Ok((stream, local_addr, remote_addr))).await;
+                        }
+                        Ok(Err(error)) => debug!("TLS handshake with {} failed: {}", remote_addr, error),
+                        Err(_) => debug!("TLS handshake with {} timed out", remote_addr),
+                    }
+                });
+            }
+        });
+
+        Ok(TlsAcceptor { local_addr, receiver })
+    }
+}
+
+/// Hands out the connections of the inner acceptor `A` once their handshake completed
+pub struct TlsAcceptor<A: Acceptor> {
+    local_addr: Vec<LocalAddr>,
+    receiver: Receiver<Accepted<A::Io>>,
+}
+
+impl<A: Acceptor> Acceptor for TlsAcceptor<A> {
+    type Io = TlsStream<A::Io>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.local_addr.clone()
+    }
+
+    async fn accept(&mut self) -> IoResult<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        let (stream, local_addr, remote_addr) = self
+            .receiver
+            .recv()
+            .await
+            .map_err(|_| IoError::other("TLS listener stopped"))??;
+
+        Ok((stream, local_addr, remote_addr, Scheme::HTTPS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn self_signed(name: &str) -> (String, String) {
+        let certificate = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+
+        (certificate.cert.pem(), certificate.key_pair.serialize_pem())
+    }
+
+    #[test]
+    fn test_lookup() {
+        let resolver = CertificateResolver::default();
+
+        for name in ["luc.computer", "*.luc.computer"] {
+            let (certificate, key) = self_signed(name);
+            resolver.insert(name, Arc::new(certified_key(&certificate, key.as_bytes()).unwrap()));
+        }
+
+        let exact = resolver.lookup("luc.computer").unwrap();
+        let wildcard = resolver.lookup("Hello.luc.computer.").unwrap();
+
+        assert!(!Arc::ptr_eq(&exact, &wildcard));
+        assert!(resolver.lookup("deep.hello.luc.computer").is_none());
+        assert!(resolver.lookup("computer").is_none());
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let (certificate, key) = self_signed("luc.computer");
+
+        assert!(expires_at(&certificate).unwrap() > Utc::now());
+        assert!(certified_key(&certificate, b"").is_err());
+        assert!(certified_key("", key.as_bytes()).is_err());
+    }
+}
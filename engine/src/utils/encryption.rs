@@ -0,0 +1,51 @@
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    error::Unspecified,
+};
+
+/// Encrypts with AES-256-GCM under a random nonce, returns the hex encoded nonce followed by the ciphertext
+pub fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<String, Unspecified> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key)?);
+    let nonce = rand::random::<[u8; NONCE_LEN]>();
+
+    let mut sealed = plaintext.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)?;
+
+    Ok(hex::encode([nonce.as_slice(), &sealed].concat()))
+}
+
+/// Reverses `encrypt`, fails when the value was encrypted with another key or altered
+pub fn decrypt(key: &[u8; 32], value: &str) -> Result<Vec<u8>, Unspecified> {
+    let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key)?);
+    let data = hex::decode(value).map_err(|_| Unspecified)?;
+
+    if data.len() < NONCE_LEN {
+        return Err(Unspecified);
+    }
+
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let mut sealed = sealed.to_vec();
+    let plaintext = key.open_in_place(Nonce::try_assume_unique_for_key(nonce)?, Aad::empty(), &mut sealed)?;
+
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let key = [7u8; 32];
+        let encrypted = encrypt(&key, b"private key").unwrap();
+
+        assert_ne!(encrypted, encrypt(&key, b"private key").unwrap());
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), b"private key");
+        assert!(decrypt(&[8u8; 32], &encrypted).is_err());
+
+        let mut altered = hex::decode(&encrypted).unwrap();
+        *altered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&key, &hex::encode(altered)).is_err());
+        assert!(decrypt(&key, "00").is_err());
+    }
+}
@@ -1,3 +1,7 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
 use sha2::{Digest, Sha256};
 
 #[tracing::instrument(name = "hash_password", skip(password))]
@@ -20,3 +24,20 @@ pub fn hash_session(session: impl AsRef<str>) -> String {
     let hash = hasher.finalize();
     hex::encode(hash)
 }
+
+/// Hashes a site access password with argon2 and a random salt, returns the PHC string
+#[tracing::instrument(name = "hash_access_password", skip(password))]
+pub fn hash_access_password(password: impl AsRef<str>) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(Argon2::default()
+        .hash_password(password.as_ref().as_bytes(), &salt)?
+        .to_string())
+}
+
+#[tracing::instrument(name = "verify_access_password", skip(password, hash))]
+pub fn verify_access_password(password: impl AsRef<str>, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_ref().as_bytes(), &hash))
+        .is_ok()
+}
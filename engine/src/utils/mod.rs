@@ -1,3 +1,4 @@
 pub mod id;
 pub mod hash;
+pub mod encryption;
 pub mod build_info;
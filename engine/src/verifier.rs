@@ -0,0 +1,310 @@
+use std::{net::IpAddr, time::Duration};
+
+use chrono::Utc;
+use tracing::{error, info, warn};
+
+use crate::{
+    models::domain::{challenge_record_name, Domain, DomainPending, DomainSubmission},
+    state::{DomainsConfig, State},
+};
+
+/// Pending and active domains handled per run
+const BATCH_SIZE: i64 = 100;
+/// Label looked up below a wildcard domain when re-checking it, any name under the wildcard record resolves the same
+const WILDCARD_PROBE_LABEL: &str = "edgeserver-probe";
+
+/// What active domains have to point at to be considered ours
+#[derive(Debug, Default)]
+struct Targets {
+    cname_target: Option<String>,
+    addresses: Vec<IpAddr>,
+}
+
+impl Targets {
+    fn from_config(config: &DomainsConfig) -> Self {
+        let addresses = config
+            .addresses
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .filter_map(|address| match address.parse::<IpAddr>() {
+                Ok(address) => Some(address),
+                Err(_) => {
+                    warn!("Ignoring invalid address in DOMAINS_ADDRESSES: {:?}", address);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            cname_target: config
+                .cname_target
+                .as_deref()
+                .map(|target| target.trim_end_matches('.').to_lowercase()),
+            addresses,
+        }
+    }
+}
+
+/// Checks pending domains with backoff, expires stale ones and re-checks active domains every `DOMAINS_RECHECK_INTERVAL_HOURS`
+pub async fn run_scheduled(state: State) {
+    let default_config = DomainsConfig::default();
+    let config = state.config.domains.as_ref().unwrap_or(&default_config);
+    let targets = Targets::from_config(config);
+
+    if targets.cname_target.is_none() && targets.addresses.is_empty() {
+        info!("Active domains are only re-checked against their TXT record, set DOMAINS_CNAME_TARGET or DOMAINS_ADDRESSES to check where they point");
+    }
+
+    loop {
+        if let Err(error) = verify_pending(&state, config).await {
+            error!("Verifying pending domains failed: {:?}", error);
+        }
+
+        if let Err(error) = expire_pending(&state, config).await {
+            error!("Expiring pending domains failed: {:?}", error);
+        }
+
+        if let Err(error) = recheck_domains(&state, config, &targets).await {
+            error!("Re-checking domains failed: {:?}", error);
+        }
+
+        async_std::task::sleep(Duration::from_secs(config.poll_interval_seconds)).await;
+    }
+}
+
+async fn verify_pending(state: &State, config: &DomainsConfig) -> Result<(), sqlx::Error> {
+    for pending in DomainPending::due(BATCH_SIZE, state).await? {
+        match pending.do_challenge(state).await {
+            Ok(DomainSubmission::Verified(domain)) => {
+                info!("Verified domain {} for site {}", domain.domain, domain.site_id);
+            }
+            Ok(DomainSubmission::Pending(pending)) => {
+                schedule_retry(state, config, &pending, pending.attempts).await?;
+            }
+            Err(error) => {
+                // moved back in the queue so one broken entry doesn't hold up the others
+                warn!(
+                    "Checking the challenge of {} for site {} failed: {:?}",
+                    pending.domain, pending.site_id, error
+                );
+
+                schedule_retry(state, config, &pending, pending.attempts + 1).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn schedule_retry(
+    state: &State,
+    config: &DomainsConfig,
+    pending: &DomainPending,
+    attempts: i32,
+) -> Result<(), sqlx::Error> {
+    let delay = retry_delay(config, attempts);
+    let next_attempt_at = Utc::now() + chrono::Duration::seconds(delay.as_secs() as i64);
+
+    pending.schedule_retry(next_attempt_at, state).await
+}
+
+async fn expire_pending(state: &State, config: &DomainsConfig) -> Result<(), sqlx::Error> {
+    let cutoff = Utc::now() - chrono::Duration::days(config.pending_expiry_days);
+    let expired = DomainPending::expire_stale(cutoff, state).await?;
+
+    if !expired.is_empty() {
+        info!("Expired {} pending domains older than {} days", expired.len(), config.pending_expiry_days);
+    }
+
+    Ok(())
+}
+
+async fn recheck_domains(state: &State, config: &DomainsConfig, targets: &Targets) -> Result<(), sqlx::Error> {
+    let cutoff = Utc::now() - chrono::Duration::hours(config.recheck_interval_hours);
+
+    let detach_after_failures = config.detach_after_failures();
+
+    for domain in Domain::due_for_recheck(cutoff, BATCH_SIZE, state).await? {
+        let failure_reason = match check_domain(state, targets, &domain).await {
+            Check::Passed => None,
+            Check::Failed(reason) => Some(reason),
+            Check::Inconclusive(reason) => {
+                // resolver trouble says nothing about the domain, it is checked again on the next run
+                warn!("Could not re-check domain {} of site {}: {}", domain.domain, domain.site_id, reason);
+                continue;
+            }
+        };
+
+        let Some(checked) = domain.record_check(failure_reason.as_deref(), state).await? else {
+            continue;
+        };
+
+        let Some(failure_reason) = &checked.failure_reason else {
+            continue;
+        };
+
+        warn!(
+            "Domain {} of site {} failed {} checks: {}",
+            checked.domain, checked.site_id, checked.check_failures, failure_reason
+        );
+
+        if detach_after_failures > 0 && checked.check_failures >= detach_after_failures {
+            checked.detach(failure_reason, state).await?;
+
+            warn!("Detached domain {} from site {}", checked.domain, checked.site_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// The outcome of re-checking an active domain
+#[derive(Debug, PartialEq, Eq)]
+enum Check {
+    Passed,
+    /// Every lookup answered and none pointed at the engine or held the challenge
+    Failed(String),
+    /// Nothing passed but a lookup errored, so the domain may still be fine
+    Inconclusive(String),
+}
+
+/// Checks that a domain still points at the engine or still holds its challenge
+///
+/// Passing any one check is enough. Domains without a challenge pass when no targets are configured.
+async fn check_domain(state: &State, targets: &Targets, domain: &Domain) -> Check {
+    let name = probe_name(&domain.domain);
+    let mut reasons = Vec::new();
+    let mut lookup_failed = false;
+
+    if let Some(target) = &targets.cname_target {
+        match state.dns.cname_targets(&name).await {
+            Ok(cnames) if cnames.contains(target) => return Check::Passed,
+            Ok(cnames) if cnames.is_empty() => reasons.push(format!("{} has no CNAME record", name)),
+            Ok(cnames) => reasons.push(format!("{} is an alias of {}", name, cnames.join(", "))),
+            Err(error) => {
+                lookup_failed = true;
+                reasons.push(format!("Failed to look up the CNAME of {}: {}", name, error));
+            }
+        }
+    }
+
+    if !targets.addresses.is_empty() {
+        match state.dns.ip_addresses(&name).await {
+            Ok(addresses) if addresses.iter().any(|address| targets.addresses.contains(address)) => return Check::Passed,
+            Ok(addresses) if addresses.is_empty() => reasons.push(format!("{} has no A or AAAA records", name)),
+            Ok(addresses) => reasons.push(format!(
+                "{} resolves to {}",
+                name,
+                addresses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Err(error) => {
+                lookup_failed = true;
+                reasons.push(format!("Failed to look up the addresses of {}: {}", name, error));
+            }
+        }
+    }
+
+    if let Some(challenge) = &domain.challenge {
+        let record_name = challenge_record_name(&domain.domain);
+
+        match state.dns.txt_records(&record_name).await {
+            Ok(records) if records.iter().any(|record| record.trim() == challenge) => return Check::Passed,
+            Ok(_) => reasons.push(format!("The TXT record at {} no longer holds the challenge", record_name)),
+            Err(error) => {
+                lookup_failed = true;
+                reasons.push(format!("Failed to look up {}: {}", record_name, error));
+            }
+        }
+    }
+
+    match (reasons.is_empty(), lookup_failed) {
+        (true, _) => Check::Passed,
+        (false, true) => Check::Inconclusive(reasons.join("; ")),
+        (false, false) => Check::Failed(reasons.join("; ")),
+    }
+}
+
+/// The name looked up when re-checking a domain, wildcards are checked through a name below them
+fn probe_name(domain: &str) -> String {
+    match domain.strip_prefix("*.") {
+        Some(parent) => format!("{}.{}", WILDCARD_PROBE_LABEL, parent),
+        None => domain.to_string(),
+    }
+}
+
+/// How long to wait after the `attempts`th failed challenge check, doubling up to `retry_max_seconds`
+fn retry_delay(config: &DomainsConfig, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 32) as u32;
+    let delay = config
+        .retry_base_seconds
+        .saturating_mul(2u64.saturating_pow(exponent))
+        .min(config.retry_max_seconds);
+
+    Duration::from_secs(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let config = DomainsConfig {
+            retry_base_seconds: 60,
+            retry_max_seconds: 3600,
+            ..DomainsConfig::default()
+        };
+
+        assert_eq!(retry_delay(&config, 0), Duration::from_secs(60));
+        assert_eq!(retry_delay(&config, 1), Duration::from_secs(60));
+        assert_eq!(retry_delay(&config, 2), Duration::from_secs(120));
+        assert_eq!(retry_delay(&config, 4), Duration::from_secs(480));
+        assert_eq!(retry_delay(&config, 7), Duration::from_secs(3600));
+        assert_eq!(retry_delay(&config, i32::MAX), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_probe_name() {
+        assert_eq!(probe_name("luc.computer"), "luc.computer");
+        assert_eq!(probe_name("*.luc.computer"), "edgeserver-probe.luc.computer");
+    }
+
+    #[test]
+    fn test_detach_after_failures() {
+        assert_eq!(DomainsConfig::default().detach_after_failures(), 0);
+
+        let targeted = DomainsConfig {
+            cname_target: Some("edge.example.com".to_string()),
+            ..DomainsConfig::default()
+        };
+        assert_eq!(targeted.detach_after_failures(), 3);
+
+        let configured = DomainsConfig {
+            detach_after_failures: Some(5),
+            ..DomainsConfig::default()
+        };
+        assert_eq!(configured.detach_after_failures(), 5);
+    }
+
+    #[test]
+    fn test_targets_from_config() {
+        let targets = Targets::from_config(&DomainsConfig {
+            cname_target: Some("Edge.Example.com.".to_string()),
+            addresses: Some("203.0.113.10, nonsense,2001:db8::10".to_string()),
+            ..DomainsConfig::default()
+        });
+
+        assert_eq!(targets.cname_target.as_deref(), Some("edge.example.com"));
+        assert_eq!(
+            targets.addresses,
+            vec!["203.0.113.10".parse::<IpAddr>().unwrap(), "2001:db8::10".parse().unwrap()]
+        );
+    }
+}
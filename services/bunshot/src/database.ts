@@ -52,6 +52,11 @@ export async function insertDeploymentPreview(
       [siteId, deploymentId, filePath, fullPreviewPath, faviconPath, mimeType]
     );
 
+    await client.query(
+      "UPDATE deployments SET preview_status = 'ready' WHERE deployment_id = $1",
+      [deploymentId]
+    );
+
     client.release();
 
     console.log('Deployment preview saved to database:', result.rows[0]);
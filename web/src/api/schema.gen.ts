@@ -537,7 +537,7 @@ export type paths = {
         patch?: never;
         trace?: never;
     };
-    "/site/{site_id}/domains/{domain}/preflight": {
+    "/site/{site_id}/domains/preflight": {
         parameters: {
             query?: never;
             header?: never;
@@ -546,16 +546,19 @@ export type paths = {
         };
         /**
          * Preflight check a site domain
-         * @description Checks wether or not the domain will require validation
-         *     It does so by checking overlap
+         * @description Reports the existing domains adding this domain would affect, on this or other sites.
+         *     `invalidates` are taken over once the domain is verified (the same name, or every domain below a wildcard),
+         *     `overrides` are wildcards above it that keep existing but no longer serve this name.
+         *     Adding the domain requires verification when either is non-empty
          */
         get: {
             parameters: {
-                query?: never;
+                query: {
+                    domain: string;
+                };
                 header?: never;
                 path: {
                     site_id: string;
-                    domain: string;
                 };
                 cookie?: never;
             };
@@ -1747,10 +1750,19 @@ export type components = {
             /** Format: date-time */
             updated_at: string;
         };
+        /**
+         * DomainOverlap
+         * @description An existing domain the preflighted name overlaps with, other sites' details are left out
+         */
+        DomainOverlap: {
+            domain: string;
+            /** @description The domain belongs to the site the preflight was made for */
+            own_site: boolean;
+        };
         /** DomainPreflightResponseData */
         DomainPreflightResponseData: {
-            overrides: components["schemas"]["Domain"][];
-            invalidates: components["schemas"]["Domain"][];
+            overrides: components["schemas"]["DomainOverlap"][];
+            invalidates: components["schemas"]["DomainOverlap"][];
         };
         DomainSubmission: components["schemas"]["DomainPending"] | components["schemas"]["Domain"];
         /** IPFSStatus */
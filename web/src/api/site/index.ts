@@ -85,6 +85,7 @@ export const useSiteDeployments = (siteId?: string) => {
 
 export type DomainSubmission = components['schemas']['DomainSubmission'];
 export type Domain = components['schemas']['Domain'];
+export type DomainOverlap = components['schemas']['DomainOverlap'];
 
 export const getSiteDomains = (siteId?: string) =>
     queryOptions({
@@ -125,10 +126,11 @@ export const getSiteDomainPreflight = (siteId?: string, domain?: string) =>
             if (!siteId || !domain) return { error: 'No siteId or domain' };
 
             const response = await apiRequest(
-                '/site/{site_id}/domains/{domain}/preflight',
+                '/site/{site_id}/domains/preflight',
                 'get',
                 {
-                    path: { site_id: siteId, domain: domain },
+                    path: { site_id: siteId },
+                    query: { domain },
                 }
             );
 
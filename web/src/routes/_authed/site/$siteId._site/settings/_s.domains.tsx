@@ -13,6 +13,7 @@ import {
 
 import {
   Domain,
+  DomainOverlap,
   DomainSubmission,
   useSiteDomainCreate,
   useSiteDomainDelete,
@@ -232,9 +233,9 @@ export const SiteDomainCreateModal: FC<
                   within/overlaps the jurisdiction of the following:
                 </p>
                 <ul className="space-y-1">
-                  {preflight.overrides.map((domain) => (
-                    <li key={domain.domain}>
-                      <MiniDomainPreview domain={domain} showSiteId={true} />
+                  {preflight.overrides.map((overlap) => (
+                    <li key={overlap.domain}>
+                      <DomainOverlapPreview overlap={overlap} />
                     </li>
                   ))}
                 </ul>
@@ -248,9 +249,9 @@ export const SiteDomainCreateModal: FC<
                   <b>out-prioritize</b> your domain:
                 </p>
                 <ul className="space-y-1">
-                  {preflight.invalidates.map((domain) => (
-                    <li key={domain.domain}>
-                      <MiniDomainPreview domain={domain} showSiteId={true} />
+                  {preflight.invalidates.map((overlap) => (
+                    <li key={overlap.domain}>
+                      <DomainOverlapPreview overlap={overlap} />
                     </li>
                   ))}
                 </ul>
@@ -301,3 +302,19 @@ export const MiniDomainPreview: FC<{
     </Link>
   )
 }
+
+export const DomainOverlapPreview: FC<{ overlap: DomainOverlap }> = ({
+  overlap,
+}) => {
+  return (
+    <div className="card no-padding flex items-center justify-between gap-2 p-2">
+      <div className="flex items-center gap-2">
+        <LuGlobe />
+        <div className="truncate">{overlap.domain}</div>
+      </div>
+      <div className="text-muted">
+        {overlap.own_site ? 'This site' : 'Another site'}
+      </div>
+    </div>
+  )
+}